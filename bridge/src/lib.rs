@@ -0,0 +1,19 @@
+#[macro_use(bson, doc)]
+extern crate bson;
+extern crate chan;
+extern crate mongodb;
+extern crate proddle;
+extern crate rusqlite;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate slog;
+#[macro_use]
+extern crate slog_scope;
+extern crate time;
+
+pub mod metrics;
+mod server;
+pub mod store;
+
+pub use server::Bridge;
@@ -1,9 +1,7 @@
-extern crate bson;
-#[macro_use]
-extern crate chan;
+extern crate bridge;
+extern crate chan_signal;
 #[macro_use]
 extern crate clap;
-extern crate mongodb;
 extern crate proddle;
 #[macro_use]
 extern crate slog;
@@ -11,147 +9,104 @@ extern crate slog;
 extern crate slog_scope;
 extern crate slog_term;
 
-use chan::Receiver;
+use bridge::Bridge;
+use bridge::metrics::Metrics;
+use bridge::store::{MemoryStore, MongoStore, SqliteStore, Store};
+use chan_signal::Signal;
 use clap::{App, ArgMatches};
-use proddle::{Message, MessageType, ProddleError};
+use proddle::ProddleError;
 use slog::{DrainExt, Logger};
 
-mod db_wrapper;
-use db_wrapper::DbWrapper;
-
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
 
-fn parse_args(matches: &ArgMatches) -> Result<(SocketAddr, String, u16, String, String, String, String, String), ProddleError> {
+fn parse_args(matches: &ArgMatches) -> Result<(SocketAddr, Option<String>), ProddleError> {
     let bridge_ip_address = try!(value_t!(matches, "BRIDGE_IP_ADDRESS", String));
     let bridge_port = try!(value_t!(matches.value_of("BRIDGE_PORT"), u16));
     let bridge_address = try!(SocketAddr::from_str(&format!("{}:{}", bridge_ip_address, bridge_port)));
-    let mongodb_ip_address = try!(value_t!(matches, "MONGODB_IP_ADDRESS", String));
-    let mongodb_port = try!(value_t!(matches.value_of("MONGODB_PORT"), u16));
-    let ca_file = try!(value_t!(matches.value_of("CA_FILE"), String));
-    let certificate_file = try!(value_t!(matches.value_of("CERTIFICATE_FILE"), String));
-    let key_file = try!(value_t!(matches.value_of("KEY_FILE"), String));
-    let username = try!(value_t!(matches.value_of("USERNAME"), String));
-    let password = try!(value_t!(matches.value_of("PASSWORD"), String));
-
-    Ok((bridge_address, mongodb_ip_address, mongodb_port, ca_file, certificate_file, key_file, username, password))
+    let metrics_address = match matches.value_of("METRICS_ADDRESS") {
+        Some(metrics_address) => {
+            try!(SocketAddr::from_str(metrics_address).map_err(|_| format!("invalid metrics address '{}'", metrics_address)));
+            Some(metrics_address.to_owned())
+        },
+        None => None,
+    };
+
+    Ok((bridge_address, metrics_address))
+}
+
+fn open_store(matches: &ArgMatches) -> Result<Box<Store>, ProddleError> {
+    match matches.value_of("STORE") {
+        Some("memory") => Ok(Box::new(MemoryStore::new())),
+        Some("sqlite") => {
+            let sqlite_file = try!(value_t!(matches.value_of("SQLITE_FILE"), String));
+            Ok(Box::new(try!(SqliteStore::new(&sqlite_file))))
+        },
+        _ => {
+            let mongodb_ip_address = try!(value_t!(matches, "MONGODB_IP_ADDRESS", String));
+            let mongodb_port = try!(value_t!(matches.value_of("MONGODB_PORT"), u16));
+            let ca_file = try!(value_t!(matches.value_of("CA_FILE"), String));
+            let certificate_file = try!(value_t!(matches.value_of("CERTIFICATE_FILE"), String));
+            let key_file = try!(value_t!(matches.value_of("KEY_FILE"), String));
+            let username = try!(value_t!(matches.value_of("USERNAME"), String));
+            let password = try!(value_t!(matches.value_of("PASSWORD"), String));
+
+            Ok(Box::new(try!(MongoStore::new(&mongodb_ip_address, mongodb_port, &username,
+                                             &password, &ca_file, &certificate_file, &key_file))))
+        },
+    }
 }
 
 pub fn main() {
     slog_scope::set_global_logger(Logger::root(slog_term::streamer().build().fuse(), o![]));
+
+    //register for signals before any threads are started so every thread blocks them
+    let signal_rx = chan_signal::notify(&[Signal::INT, Signal::TERM]);
+
     let yaml = load_yaml!("args.yaml");
     let matches = App::from_yaml(yaml).get_matches();
 
     //initialize bridge parameters
     info!("parsing command line arguments");
-    let (socket_addr, mongodb_ip_address, mongodb_port, ca_file, certificate_file, key_file, username, password) = match parse_args(&matches) {
+    let (socket_addr, metrics_address) = match parse_args(&matches) {
         Ok(args) => args,
         Err(e) => panic!("{}", e),
     };
 
-    //connect to mongodb client
-    let db_wrapper = match DbWrapper::new(&mongodb_ip_address, mongodb_port, &username, 
-                                                  &password, &ca_file, &certificate_file, &key_file) {
-        Ok(db_wrapper) => Arc::new(RwLock::new(db_wrapper)),
-        Err(e) => panic!("failed to initialize db_wrapper: {}", e),
+    //open storage backend
+    let store = match open_store(&matches) {
+        Ok(store) => store,
+        Err(e) => panic!("failed to initialize store: {}", e),
     };
 
-    //start stream threadpool
-    info!("starting threadpool");
-    let (stream_tx, stream_rx) = chan::sync(0);
-    for _ in 0..8 {
-        let t_stream_rx: Receiver<TcpStream> = stream_rx.clone();
-        let t_db_wrapper = db_wrapper.clone();
-        let _ = std::thread::spawn(move || {
-            loop {
-                chan_select! {
-                    t_stream_rx.recv() -> stream => {
-                        match stream {
-                            Some(mut stream) => {
-                                let db_wrapper = t_db_wrapper.read().unwrap();
-                                if let Err(e) = handle_stream(&mut stream, &db_wrapper) {
-                                    error!("{}", e);
-                                }
-                            },
-                            None => error!("failed to recv stream"),
-                        }
-                    },
-                }
-            }
-        });
+    //start metrics endpoint
+    let metrics = Arc::new(Mutex::new(Metrics::new()));
+    if let Some(ref metrics_address) = metrics_address {
+        match proddle::serve_metrics(metrics_address, metrics.clone()) {
+            Ok(_) => info!("serving metrics on '{}'", metrics_address),
+            Err(e) => panic!("failed to start metrics endpoint on '{}': {}", metrics_address, e),
+        }
     }
 
-    //start listener
-    let listener = match TcpListener::bind(socket_addr) {
-        Ok(listener) => listener,
-        Err(e) => panic!("failed to bind to address '{}': {}", socket_addr, e),
+    let bridge = match Bridge::start(socket_addr, store, metrics) {
+        Ok(bridge) => bridge,
+        Err(e) => panic!("failed to start bridge on '{}': {}", socket_addr, e),
     };
 
     info!("startup complete");
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => stream_tx.send(stream),
-            Err(e) => error!("recv connection failed: {}", e),
-        }
+    match signal_rx.recv() {
+        Some(signal) => info!("received {:?}, shutting down", signal),
+        None => error!("failed to recv signal, shutting down"),
     }
-}
 
-fn handle_stream(stream: &mut TcpStream, db_wrapper: &DbWrapper) -> Result<(), ProddleError> {
-    try!(stream.set_read_timeout(Some(Duration::new(45, 0))));
-    try!(stream.set_write_timeout(Some(Duration::new(45, 0))));
-
-    let request = try!(proddle::message_from_stream(stream));
-    match request.message_type {
-        MessageType::SendMeasurementsRequest => {
-            match request.send_measurements_request {
-                Some(measurements) => {
-                    //attempt to send measurements to db
-                    let measurement_count = measurements.len();
-                    let message = match db_wrapper.send_measurements(measurements) {
-                        Ok(measurement_failures) => {
-                            info!("{}: inserted {} measurement(s), {} measurement(s) failed", stream.peer_addr().unwrap(), 
-                                measurement_count - measurement_failures.len(), measurement_failures.len());
-                            Message::send_measurements_response(measurement_failures)
-                        },
-                        Err(e) => {
-                            error!("{}", e);
-                            Message::error(format!("{}", e))
-                        },
-                    };
-
-                    //send response
-                    try!(proddle::message_to_stream(&message, stream));
-                    Ok(())
-                },
-                None => Err(ProddleError::from("recv malformed send measurements request")),
-            }
-        },
-        MessageType::UpdateOperationsRequest => {
-            match request.update_operations_request {
-                Some(operation_bucket_hashes) => {
-                    //attempt to update operations from db
-                    let message = match db_wrapper.update_operations(operation_bucket_hashes) {
-                        Ok(operation_buckets) => {
-                            if operation_buckets.len() > 0 {
-                                info!("{}: updated {} operation bucket(s)", stream.peer_addr().unwrap(), operation_buckets.len());
-                            }
-                            Message::update_operations_response(operation_buckets)
-                        },
-                        Err(e) => {
-                            error!("{}", e);
-                            Message::error(format!("{}", e))
-                        },
-                    };
-
-                    //send response
-                    try!(proddle::message_to_stream(&message, stream));
-                    Ok(())
-                },
-                None => Err(ProddleError::from("recv malformed update operations request")),
-            }
-        },
-        _ => Err(ProddleError::from(format!("unsupported message type: '{:?}'", request.message_type)))
-    }
+    //stop accepting connections and wait for open requests to complete
+    let exit_code = match bridge.shutdown() {
+        true => 0,
+        false => 1,
+    };
+
+    info!("shutdown complete");
+    std::process::exit(exit_code);
 }
+
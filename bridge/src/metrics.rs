@@ -0,0 +1,251 @@
+use proddle::{ClockSample, MessageType, MetricsSource, write_metric};
+use time;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::Duration;
+
+//upper bounds in seconds of the measurement insert latency histogram buckets
+static INSERT_LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+//vantages that have not contacted the bridge within this many seconds are reported as inactive
+static ACTIVE_VANTAGE_SECONDS: i64 = 3600;
+
+#[derive(Default)]
+struct RequestMetrics {
+    count: u64,
+    errors: u64,
+    bytes_received: u64,
+    bytes_sent: u64,
+}
+
+impl RequestMetrics {
+    fn values(&self) -> [u64; 4] {
+        [self.count, self.errors, self.bytes_received, self.bytes_sent]
+    }
+}
+
+struct VantageMetrics {
+    hostname: Option<String>,
+    address: IpAddr,
+    first_seen: i64,
+    last_seen: i64,
+    request_count: u64,
+    measurement_count: u64,
+    open_connections: usize,
+    clock_offset_milliseconds: Option<i64>,
+    round_trip_milliseconds: Option<i64>,
+}
+
+/// Bridge state exposed by the metrics endpoint. Counters are cumulative since startup.
+pub struct Metrics {
+    start_timestamp: i64,
+    open_connections: usize,
+    requests: HashMap<String, RequestMetrics>,
+    insert_latency_buckets: Vec<u64>,
+    insert_latency_sum: f64,
+    insert_latency_count: u64,
+    inserted_measurements: u64,
+    failed_inserts: u64,
+    vantages: HashMap<String, VantageMetrics>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            start_timestamp: time::now_utc().to_timespec().sec,
+            open_connections: 0,
+            requests: HashMap::new(),
+            insert_latency_buckets: vec![0; INSERT_LATENCY_BUCKETS.len()],
+            insert_latency_sum: 0.0,
+            insert_latency_count: 0,
+            inserted_measurements: 0,
+            failed_inserts: 0,
+            vantages: HashMap::new(),
+        }
+    }
+
+    /// Records a request and returns the key identifying its vantage, the vantage id when sent
+    /// and the peer ip address otherwise.
+    pub fn record_request(&mut self, message_type: &MessageType, vantage_id: Option<&String>, hostname: Option<&String>,
+                          address: IpAddr, bytes_received: usize) -> String {
+        let now = time::now_utc().to_timespec().sec;
+        self.open_connections += 1;
+
+        let request_metrics = self.requests.entry(format!("{:?}", message_type)).or_insert(RequestMetrics::default());
+        request_metrics.count += 1;
+        request_metrics.bytes_received += bytes_received as u64;
+
+        let key = match vantage_id {
+            Some(vantage_id) => vantage_id.to_owned(),
+            None => address.to_string(),
+        };
+
+        let vantage_metrics = self.vantages.entry(key.to_owned()).or_insert(VantageMetrics {
+            hostname: None,
+            address: address,
+            first_seen: now,
+            last_seen: now,
+            request_count: 0,
+            measurement_count: 0,
+            open_connections: 0,
+            clock_offset_milliseconds: None,
+            round_trip_milliseconds: None,
+        });
+
+        if let Some(hostname) = hostname {
+            vantage_metrics.hostname = Some(hostname.to_owned());
+        }
+
+        vantage_metrics.address = address;
+        vantage_metrics.last_seen = now;
+        vantage_metrics.request_count += 1;
+        vantage_metrics.open_connections += 1;
+        key
+    }
+
+    /// Records the response to a request previously passed to 'record_request'. Requests that
+    /// failed before a response was sent are recorded with zero bytes.
+    pub fn record_response(&mut self, message_type: &MessageType, vantage_key: &str, bytes_sent: usize, error: bool) {
+        self.open_connections -= 1;
+        if let Some(request_metrics) = self.requests.get_mut(&format!("{:?}", message_type)) {
+            request_metrics.bytes_sent += bytes_sent as u64;
+            if error {
+                request_metrics.errors += 1;
+            }
+        }
+
+        if let Some(vantage_metrics) = self.vantages.get_mut(vantage_key) {
+            vantage_metrics.open_connections -= 1;
+        }
+    }
+
+    pub fn record_clock_sample(&mut self, vantage_key: &str, clock_sample: &ClockSample) {
+        if let Some(vantage_metrics) = self.vantages.get_mut(vantage_key) {
+            vantage_metrics.clock_offset_milliseconds = Some(clock_sample.offset());
+            vantage_metrics.round_trip_milliseconds = Some(clock_sample.round_trip());
+        }
+    }
+
+    pub fn record_insert(&mut self, vantage_key: &str, latency: Duration, inserted: usize, failed: usize) {
+        let seconds = latency.as_secs() as f64 + latency.subsec_nanos() as f64 / 1_000_000_000.0;
+        for (i, upper_bound) in INSERT_LATENCY_BUCKETS.iter().enumerate() {
+            if seconds <= *upper_bound {
+                self.insert_latency_buckets[i] += 1;
+            }
+        }
+
+        self.insert_latency_sum += seconds;
+        self.insert_latency_count += 1;
+        self.inserted_measurements += inserted as u64;
+        self.failed_inserts += failed as u64;
+
+        if let Some(vantage_metrics) = self.vantages.get_mut(vantage_key) {
+            vantage_metrics.measurement_count += inserted as u64;
+        }
+    }
+}
+
+impl MetricsSource for Metrics {
+    /// Renders metrics in the prometheus text exposition format.
+    fn to_prometheus(&self) -> String {
+        let mut output = String::new();
+
+        let mut message_types: Vec<&String> = self.requests.keys().collect();
+        message_types.sort();
+        for (i, &(name, help)) in [
+                ("proddle_bridge_requests_total", "Requests received by message type."),
+                ("proddle_bridge_request_errors_total", "Requests answered with an error or dropped by message type."),
+                ("proddle_bridge_received_bytes_total", "Request bytes received by message type."),
+                ("proddle_bridge_sent_bytes_total", "Response bytes sent by message type."),
+            ].iter().enumerate() {
+            let samples: Vec<(String, f64)> = message_types.iter()
+                .map(|x| (format!("type=\"{}\"", x), self.requests[*x].values()[i] as f64)).collect();
+            write_metric(&mut output, name, help, "counter", &samples);
+        }
+
+        let mut latency_samples = Vec::new();
+        for (i, upper_bound) in INSERT_LATENCY_BUCKETS.iter().enumerate() {
+            latency_samples.push((format!("_bucket{{le=\"{}\"}}", upper_bound), self.insert_latency_buckets[i] as f64));
+        }
+        latency_samples.push((String::from("_bucket{le=\"+Inf\"}"), self.insert_latency_count as f64));
+        latency_samples.push((String::from("_sum"), self.insert_latency_sum));
+        latency_samples.push((String::from("_count"), self.insert_latency_count as f64));
+        let _ = writeln!(output, "# HELP proddle_bridge_insert_latency_seconds Time to insert a batch of measurements.");
+        let _ = writeln!(output, "# TYPE proddle_bridge_insert_latency_seconds histogram");
+        for &(ref suffix, value) in latency_samples.iter() {
+            let _ = writeln!(output, "proddle_bridge_insert_latency_seconds{} {}", suffix, value);
+        }
+
+        for &(name, help, metric_type, value) in [
+                ("proddle_bridge_inserted_measurements_total", "Measurements inserted into the database.", "counter", self.inserted_measurements as f64),
+                ("proddle_bridge_failed_inserts_total", "Measurements that failed to decode or insert.", "counter", self.failed_inserts as f64),
+                ("proddle_bridge_open_connections", "Vantage connections currently being handled.", "gauge", self.open_connections as f64),
+                ("proddle_bridge_start_timestamp_seconds", "Unix timestamp the bridge started.", "gauge", self.start_timestamp as f64),
+            ].iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+
+        let mut vantage_keys: Vec<&String> = self.vantages.keys().collect();
+        vantage_keys.sort();
+        let labels: Vec<String> = vantage_keys.iter().map(|x| {
+            let hostname = self.vantages[*x].hostname.as_ref().map(|x| x.as_str()).unwrap_or("");
+            format!("vantage=\"{}\",hostname=\"{}\"", x, hostname)
+        }).collect();
+
+        let last_seen: Vec<(String, f64)> = vantage_keys.iter().zip(labels.iter())
+            .map(|(x, labels)| (labels.to_owned(), self.vantages[*x].last_seen as f64)).collect();
+        write_metric(&mut output, "proddle_bridge_vantage_last_seen_timestamp_seconds", "Unix timestamp of the latest request from each vantage.", "gauge", &last_seen);
+
+        let measurement_count: Vec<(String, f64)> = vantage_keys.iter().zip(labels.iter())
+            .map(|(x, labels)| (labels.to_owned(), self.vantages[*x].measurement_count as f64)).collect();
+        write_metric(&mut output, "proddle_bridge_vantage_measurements_total", "Measurements inserted for each vantage.", "counter", &measurement_count);
+
+        let clock_offset: Vec<(String, f64)> = vantage_keys.iter().zip(labels.iter())
+            .filter_map(|(x, labels)| self.vantages[*x].clock_offset_milliseconds.map(|offset| (labels.to_owned(), offset as f64 / 1000.0))).collect();
+        write_metric(&mut output, "proddle_bridge_vantage_clock_offset_seconds", "Bridge clock minus vantage clock estimated from the latest exchange.", "gauge", &clock_offset);
+
+        output
+    }
+
+    /// Renders the admin view of every vantage that has contacted the bridge since startup.
+    fn to_json(&self) -> String {
+        let now = time::now_utc().to_timespec().sec;
+        let mut vantage_keys: Vec<&String> = self.vantages.keys().collect();
+        vantage_keys.sort();
+
+        let vantages: Vec<_> = vantage_keys.iter().map(|x| {
+            let vantage_metrics = &self.vantages[*x];
+            json!({
+                "vantage": x,
+                "hostname": vantage_metrics.hostname,
+                "address": vantage_metrics.address.to_string(),
+                "first_seen": vantage_metrics.first_seen,
+                "last_seen": vantage_metrics.last_seen,
+                "seconds_since_seen": now - vantage_metrics.last_seen,
+                "connected": vantage_metrics.open_connections > 0,
+                "active": now - vantage_metrics.last_seen <= ACTIVE_VANTAGE_SECONDS,
+                "request_count": vantage_metrics.request_count,
+                "measurement_count": vantage_metrics.measurement_count,
+                "clock_offset_milliseconds": vantage_metrics.clock_offset_milliseconds,
+                "round_trip_milliseconds": vantage_metrics.round_trip_milliseconds,
+            })
+        }).collect();
+
+        let status = json!({
+            "start_timestamp": self.start_timestamp,
+            "uptime_seconds": now - self.start_timestamp,
+            "open_connections": self.open_connections,
+            "inserted_measurements": self.inserted_measurements,
+            "failed_inserts": self.failed_inserts,
+            "known_vantages": vantages.len(),
+            "active_vantages": self.vantages.values().filter(|x| now - x.last_seen <= ACTIVE_VANTAGE_SECONDS).count(),
+            "vantages": vantages,
+        });
+
+        status.to_string()
+    }
+}
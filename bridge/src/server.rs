@@ -0,0 +1,277 @@
+use chan;
+use chan::Receiver;
+use proddle::{self, Message, MessageType, ProddleError};
+
+use metrics::Metrics;
+use store::{OperationIndex, Store};
+
+use std;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+//milliseconds between checks for new connections and shutdown requests
+static ACCEPT_INTERVAL_MILLISECONDS: u64 = 100;
+
+//number of threads handling vantage connections
+static THREAD_COUNT: usize = 8;
+
+/// Running bridge accepting vantage connections until it is shut down.
+pub struct Bridge {
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    listener_handle: JoinHandle<()>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Bridge {
+    /// Binds the address, port 0 selects an ephemeral port, and starts handling connections.
+    pub fn start(socket_addr: SocketAddr, store: Box<Store>, metrics: Arc<Mutex<Metrics>>) -> Result<Bridge, ProddleError> {
+        let listener = try!(TcpListener::bind(socket_addr));
+        let local_addr = try!(listener.local_addr());
+        try!(listener.set_nonblocking(true));
+
+        //start stream threadpool
+        info!("starting threadpool");
+        let store: Arc<Box<Store>> = Arc::new(store);
+        let operation_index = Arc::new(OperationIndex::new());
+        let (stream_tx, stream_rx) = chan::sync(0);
+        let mut handles = Vec::new();
+        for _ in 0..THREAD_COUNT {
+            let t_stream_rx: Receiver<TcpStream> = stream_rx.clone();
+            let t_store = store.clone();
+            let t_operation_index = operation_index.clone();
+            let t_metrics = metrics.clone();
+            handles.push(std::thread::spawn(move || {
+                //handle streams until the listener closes the channel on shutdown
+                while let Some(mut stream) = t_stream_rx.recv() {
+                    if let Err(e) = handle_stream(&mut stream, &**t_store, &t_operation_index, &t_metrics) {
+                        error!("{}", e);
+                    }
+                }
+            }));
+        }
+
+        //start listener
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let t_shutdown = shutdown.clone();
+        let listener_handle = std::thread::spawn(move || {
+            while !t_shutdown.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        match stream.set_nonblocking(false) {
+                            Ok(_) => stream_tx.send(stream),
+                            Err(e) => error!("failed to configure connection: {}", e),
+                        }
+                    },
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(Duration::from_millis(ACCEPT_INTERVAL_MILLISECONDS)),
+                    Err(e) => error!("recv connection failed: {}", e),
+                }
+            }
+        });
+
+        Ok(
+            Bridge {
+                local_addr: local_addr,
+                shutdown: shutdown,
+                listener_handle: listener_handle,
+                handles: handles,
+            }
+        )
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting connections and waits for open requests to complete, returning false if
+    /// a thread panicked.
+    pub fn shutdown(self) -> bool {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let mut success = true;
+        if let Err(_) = self.listener_handle.join() {
+            error!("listener thread panicked during shutdown");
+            success = false;
+        }
+
+        for handle in self.handles {
+            if let Err(_) = handle.join() {
+                error!("stream thread panicked during shutdown");
+                success = false;
+            }
+        }
+
+        success
+    }
+}
+
+fn handle_stream(stream: &mut TcpStream, store: &Store, operation_index: &OperationIndex, metrics: &Mutex<Metrics>) -> Result<(), ProddleError> {
+    try!(stream.set_read_timeout(Some(Duration::new(45, 0))));
+    try!(stream.set_write_timeout(Some(Duration::new(45, 0))));
+
+    let (request, request_length) = try!(proddle::message_from_stream_with_length(stream));
+    let receive_timestamp = proddle::timestamp_milliseconds();
+
+    //identify the vantage in log messages by address and id
+    let peer_address = try!(stream.peer_addr());
+    let source = match request.vantage_id {
+        Some(ref vantage_id) => format!("{} ({})", peer_address, vantage_id),
+        None => format!("{}", peer_address),
+    };
+
+    let message_type = request.message_type.clone();
+    let hostname = match (&request.update_operations_request, &request.heartbeat_request) {
+        (&Some(ref update_operations_request), _) => Some(update_operations_request.vantage_hostname.to_owned()),
+        (_, &Some(ref heartbeat)) => Some(heartbeat.vantage_hostname.to_owned()),
+        _ => None,
+    };
+    let vantage_key = {
+        let mut metrics = metrics.lock().unwrap();
+        let vantage_key = metrics.record_request(&message_type, request.vantage_id.as_ref(), hostname.as_ref(), peer_address.ip(), request_length);
+        if let Some(ref clock_sample) = request.clock_sample {
+            metrics.record_clock_sample(&vantage_key, clock_sample);
+        }
+
+        vantage_key
+    };
+
+    //requests without a response are recorded as errors
+    let result = handle_request(request, receive_timestamp, stream, store, operation_index, metrics, peer_address, &source, &vantage_key);
+    match result {
+        Ok((response_length, error)) => metrics.lock().unwrap().record_response(&message_type, &vantage_key, response_length, error),
+        Err(_) => metrics.lock().unwrap().record_response(&message_type, &vantage_key, 0, true),
+    }
+
+    result.map(|_| ())
+}
+
+//returns the response length and whether the response was an error
+fn handle_request(request: Message, receive_timestamp: i64, stream: &mut TcpStream, store: &Store, operation_index: &OperationIndex, metrics: &Mutex<Metrics>,
+                  peer_address: SocketAddr, source: &str, vantage_key: &str) -> Result<(usize, bool), ProddleError> {
+    //the vantage reports the timestamps of its previous exchange to estimate its clock offset
+    let clock_sample = request.clock_sample.clone();
+    let message = match request.message_type {
+        MessageType::SendMeasurementsRequest => {
+            match request.send_measurements_request {
+                Some(measurements) => {
+                    //attempt to send measurements to db
+                    let measurement_count = measurements.len();
+                    let start = Instant::now();
+                    match store.send_measurements(measurements, clock_sample.as_ref().map(|x| x.offset())) {
+                        Ok(measurement_failures) => {
+                            metrics.lock().unwrap().record_insert(vantage_key, start.elapsed(),
+                                measurement_count - measurement_failures.len(), measurement_failures.len());
+                            info!("{}: inserted {} measurement(s), {} measurement(s) failed", source, 
+                                measurement_count - measurement_failures.len(), measurement_failures.len());
+                            Message::send_measurements_response(measurement_failures)
+                        },
+                        Err(e) => {
+                            metrics.lock().unwrap().record_insert(vantage_key, start.elapsed(), 0, measurement_count);
+                            error!("{}", e);
+                            Message::error(format!("{}", e))
+                        },
+                    }
+                },
+                None => return Err(ProddleError::from("recv malformed send measurements request")),
+            }
+        },
+        MessageType::UpdateOperationsRequest => {
+            match request.update_operations_request {
+                Some(ref update_operations_request) if update_operations_request.hash_version != proddle::HASH_VERSION => {
+                    //bucket hashes computed with another version never match and would resend every bucket.
+                    //bincode is not self describing, so requests from vantages predating this field fail
+                    //to decode instead and only later hash versions are rejected here
+                    warn!("{}: unsupported hash version {}, bridge uses {}", source, update_operations_request.hash_version, proddle::HASH_VERSION);
+                    Message::error(format!("unsupported hash version {}, bridge uses {}", update_operations_request.hash_version, proddle::HASH_VERSION))
+                },
+                Some(update_operations_request) => {
+                    //attempt to update operations from the index and vantage configuration from db
+                    let result = operation_index.update_operations(store, update_operations_request.operation_bucket_hashes,
+                                                                   update_operations_request.operations_version,
+                                                                   &update_operations_request.tag_filter).and_then(|operation_update| {
+                        let vantage_config = try!(store.get_vantage_config(&update_operations_request.vantage_hostname,
+                                                                                &update_operations_request.vantage_groups));
+                        Ok((operation_update, vantage_config))
+                    });
+
+                    match result {
+                        Ok(((operation_buckets, operation_delta, operations_version), vantage_config)) => {
+                            if operation_buckets.len() > 0 {
+                                info!("{}: updated {} operation bucket(s)", source, operation_buckets.len());
+                            }
+
+                            if let Some(ref operation_delta) = operation_delta {
+                                let change_count: usize = operation_delta.operations.values().map(|x| x.len()).sum();
+                                if change_count > 0 {
+                                    info!("{}: sent {} operation change(s) up to version {}", source, change_count, operations_version);
+                                }
+                            }
+
+                            //only send the configuration if the vantage is running a different version
+                            let vantage_config = match vantage_config.timestamp == update_operations_request.config_version {
+                                true => None,
+                                false => {
+                                    info!("{}: sending configuration version {} to vantage '{}'", source,
+                                        vantage_config.timestamp, update_operations_request.vantage_hostname);
+                                    Some(vantage_config)
+                                },
+                            };
+
+                            //echo the observed address so vantages behind nat learn their public address
+                            Message::update_operations_response(operation_buckets, operation_delta, operations_version,
+                                vantage_config, Some(peer_address.ip().to_string()))
+                        },
+                        Err(e) => {
+                            error!("{}", e);
+                            Message::error(format!("{}", e))
+                        },
+                    }
+                },
+                None => return Err(ProddleError::from("recv malformed update operations request")),
+            }
+        },
+        MessageType::SendStatisticsRequest => {
+            match request.send_statistics_request {
+                Some(statistics) => {
+                    //attempt to send statistics to db
+                    match store.send_statistics(statistics) {
+                        Ok(_) => Message::send_statistics_response(),
+                        Err(e) => {
+                            error!("{}", e);
+                            Message::error(format!("{}", e))
+                        },
+                    }
+                },
+                None => return Err(ProddleError::from("recv malformed send statistics request")),
+            }
+        },
+        MessageType::HeartbeatRequest => {
+            match (request.heartbeat_request, request.vantage_id) {
+                (Some(heartbeat), Some(vantage_id)) => {
+                    //attempt to record heartbeat in db
+                    match store.send_heartbeat(&vantage_id, &peer_address.ip().to_string(), &heartbeat, clock_sample.as_ref(), receive_timestamp / 1000) {
+                        Ok(_) => Message::heartbeat_response(),
+                        Err(e) => {
+                            error!("{}", e);
+                            Message::error(format!("{}", e))
+                        },
+                    }
+                },
+                _ => return Err(ProddleError::from("recv malformed heartbeat request")),
+            }
+        },
+        _ => return Err(ProddleError::from(format!("unsupported message type: '{:?}'", request.message_type))),
+    };
+
+    //send response
+    let error = match message.message_type {
+        MessageType::Error => true,
+        _ => false,
+    };
+    let message = message.with_timestamps(Some(receive_timestamp), proddle::timestamp_milliseconds());
+    let length = try!(proddle::message_to_stream(&message, stream));
+    Ok((length, error))
+}
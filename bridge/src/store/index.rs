@@ -0,0 +1,448 @@
+use proddle::{self, BucketRing, Operation, OperationDelta, ProddleError, TagFilter};
+
+use store::{OperationsVersion, Store};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+//seconds between checks of the store for changed operations
+static CHANGE_CHECK_INTERVAL_SECONDS: u64 = 1;
+
+//seconds after which operations are reloaded even if no change was detected, catching edits
+//made outside the store which assign no new version
+static RELOAD_INTERVAL_SECONDS: u64 = 300;
+
+//selected operations and hashes of each bucket for one bucket count and set of vantage tags
+struct OperationBuckets {
+    hashes: HashMap<u64, u64>,
+    operations: HashMap<u64, Vec<Operation>>,
+}
+
+struct IndexState {
+    operations: Vec<(u64, Operation)>,
+    version_order: Vec<usize>,
+    operations_version: Option<OperationsVersion>,
+    purged_version: i64,
+    last_check: Option<Instant>,
+    last_reload: Option<Instant>,
+    buckets: HashMap<(u64, TagFilter), Arc<OperationBuckets>>,
+}
+
+/// In memory copy of the store's operations with the per bucket hashes vantages compare
+/// against. Operations are reloaded when the store reports a change so update requests are
+/// answered without reading every operation from the store.
+pub struct OperationIndex {
+    state: Mutex<IndexState>,
+}
+
+impl OperationIndex {
+    pub fn new() -> OperationIndex {
+        OperationIndex {
+            state: Mutex::new(
+                IndexState {
+                    operations: Vec::new(),
+                    version_order: Vec::new(),
+                    operations_version: None,
+                    purged_version: 0,
+                    last_check: None,
+                    last_reload: None,
+                    buckets: HashMap::new(),
+                }
+            ),
+        }
+    }
+
+    /// Returns the full buckets and the delta to send a vantage along with the current operations
+    /// version. Operations changed since the vantage's version are sent as a delta and any other
+    /// bucket whose hash differs from the vantage's is sent in full, recovering vantages which
+    /// diverged, have not synchronized before or are behind purged tombstones. Only operations
+    /// the tag filter selects are sent and hashed.
+    pub fn update_operations(&self, store: &Store, operation_bucket_hashes: HashMap<u64, u64>, operations_version: Option<i64>,
+                             tag_filter: &TagFilter)
+            -> Result<(HashMap<u64, Vec<Operation>>, Option<OperationDelta>, i64), ProddleError> {
+        let bucket_ring = try!(BucketRing::from_keys(operation_bucket_hashes.keys()));
+
+        //the lock is held while reloading so concurrent requests wait for one reload
+        let mut state = self.state.lock().unwrap();
+        try!(refresh(&mut state, store));
+        let operation_buckets = try!(operation_buckets(&mut state, &bucket_ring, tag_filter));
+        let version = state.operations_version.map_or(0, |x| x.version);
+
+        //a vantage ahead of the bridge synchronized with a different store and a vantage behind the
+        //purged version may hold purged operations, neither gets a delta
+        let operation_delta = match operations_version {
+            Some(operations_version) if operations_version < state.purged_version => {
+                debug!("operations version {} is behind purged version {}, recovering by bucket hashes", operations_version, state.purged_version);
+                None
+            },
+            Some(operations_version) if operations_version <= version => {
+                let mut operations: HashMap<u64, Vec<Operation>> = HashMap::new();
+                let start = state.version_order.iter().position(|x| state.operations[*x].1.version > operations_version)
+                    .unwrap_or(state.version_order.len());
+                for index in state.version_order[start..].iter() {
+                    let (domain_hash, ref operation) = state.operations[*index];
+                    let bucket_key = bucket_ring.bucket_key(domain_hash);
+
+                    //the vantage may hold an unselected operation from before it changed, so it is sent as a tombstone
+                    let mut operation = operation.clone();
+                    if !tag_filter.matches(&operation) {
+                        operation.deleted = true;
+                    }
+
+                    operations.entry(bucket_key).or_insert(Vec::new()).push(operation);
+                }
+
+                let mut hashes = HashMap::new();
+                for bucket_key in operations.keys() {
+                    hashes.insert(*bucket_key, *try!(operation_buckets.hashes.get(bucket_key).ok_or("failed to retrieve bucket hash")));
+                }
+
+                Some(
+                    OperationDelta {
+                        operations: operations,
+                        operation_bucket_hashes: hashes,
+                    }
+                )
+            },
+            _ => None,
+        };
+
+        //send the remaining buckets where the vantage hash differs from the bridge hash
+        let mut operations = HashMap::new();
+        for (bucket_key, hash) in operation_bucket_hashes.iter() {
+            if operation_delta.as_ref().map_or(false, |x| x.operations.contains_key(bucket_key)) {
+                continue;
+            }
+
+            if operation_buckets.hashes.get(bucket_key) != Some(hash) {
+                let bucket_operations = try!(operation_buckets.operations.get(bucket_key).ok_or("failed to retrieve bucket"));
+                operations.insert(*bucket_key, bucket_operations.clone());
+            }
+        }
+
+        Ok((operations, operation_delta, version))
+    }
+}
+
+//bucket the operations for a set of bucket keys, cached until operations are reloaded
+fn operation_buckets(state: &mut IndexState, bucket_ring: &BucketRing, tag_filter: &TagFilter) -> Result<Arc<OperationBuckets>, ProddleError> {
+    let cache_key = (bucket_ring.bucket_count(), tag_filter.clone());
+    if let Some(operation_buckets) = state.buckets.get(&cache_key) {
+        return Ok(operation_buckets.clone());
+    }
+
+
+    let mut operations: HashMap<u64, Vec<Operation>> = HashMap::new();
+    for bucket_key in bucket_ring.keys().iter() {
+        operations.insert(*bucket_key, Vec::new());
+    }
+
+    for &(domain_hash, ref operation) in state.operations.iter().filter(|x| !x.1.deleted && tag_filter.matches(&x.1)) {
+        let vec = try!(operations.get_mut(&bucket_ring.bucket_key(domain_hash)).ok_or("failed to retrieve bucket"));
+        vec.push(operation.clone());
+    }
+
+    let operation_buckets = Arc::new(
+        OperationBuckets {
+            hashes: operations.iter().map(|(bucket_key, operations)| (*bucket_key, proddle::bucket_digest(operations))).collect(),
+            operations: operations,
+        }
+    );
+
+    state.buckets.insert(cache_key, operation_buckets.clone());
+    Ok(operation_buckets)
+}
+
+//reload operations if the store reports a change, checking at most once per check interval
+fn refresh(state: &mut IndexState, store: &Store) -> Result<(), ProddleError> {
+    let reload_due = state.last_reload.map_or(true, |x| x.elapsed() >= Duration::from_secs(RELOAD_INTERVAL_SECONDS));
+    let check_due = state.last_check.map_or(true, |x| x.elapsed() >= Duration::from_secs(CHANGE_CHECK_INTERVAL_SECONDS));
+    if !reload_due && !check_due {
+        return Ok(());
+    }
+
+    let operations_version = try!(store.operations_version());
+    state.last_check = Some(Instant::now());
+    if !reload_due && state.operations_version == Some(operations_version) {
+        return Ok(());
+    }
+
+    //the purged version is read after the operations so it covers any tombstone missing from them
+    let operations = try!(store.operations());
+    let purged_version = try!(store.purged_version());
+    debug!("reloaded {} operation(s) into the operation index", operations.len());
+    state.operations = operations.into_iter().map(|x| (proddle::hash_string(&x.domain), x)).collect();
+    let mut version_order: Vec<usize> = (0..state.operations.len()).collect();
+    version_order.sort_by_key(|x| state.operations[*x].1.version);
+    state.version_order = version_order;
+    state.operations_version = Some(operations_version);
+    state.purged_version = purged_version;
+    state.last_reload = Some(Instant::now());
+    state.buckets.clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::Document;
+    use proddle::{ClockSample, Heartbeat, Parameter, VantageConfig};
+    use store::MemoryStore;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    //memory store counting the number of times every operation is read
+    struct CountingStore {
+        store: MemoryStore,
+        loads: AtomicUsize,
+    }
+
+    impl CountingStore {
+        fn new() -> CountingStore {
+            CountingStore {
+                store: MemoryStore::new(),
+                loads: AtomicUsize::new(0),
+            }
+        }
+
+        fn loads(&self) -> usize {
+            self.loads.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Store for CountingStore {
+        fn operations(&self) -> Result<Vec<Operation>, ProddleError> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            self.store.operations()
+        }
+
+        fn operations_version(&self) -> Result<OperationsVersion, ProddleError> {
+            self.store.operations_version()
+        }
+
+        fn purged_version(&self) -> Result<i64, ProddleError> {
+            self.store.purged_version()
+        }
+
+        fn insert_measurements(&self, measurements: Vec<Document>) -> Result<Vec<usize>, ProddleError> {
+            self.store.insert_measurements(measurements)
+        }
+
+        fn insert_statistics(&self, statistics: Document) -> Result<(), ProddleError> {
+            self.store.insert_statistics(statistics)
+        }
+
+        fn vantage_configs(&self) -> Result<Vec<VantageConfig>, ProddleError> {
+            self.store.vantage_configs()
+        }
+
+        fn send_heartbeat(&self, vantage_id: &str, ip_address: &str, heartbeat: &Heartbeat, clock_sample: Option<&ClockSample>,
+                          timestamp: i64) -> Result<(), ProddleError> {
+            self.store.send_heartbeat(vantage_id, ip_address, heartbeat, clock_sample, timestamp)
+        }
+    }
+
+    fn operation(domain: &str) -> Operation {
+        Operation {
+            version: 0,
+            deleted: false,
+            timestamp: 1500000000,
+            measurement_class: String::from("HttpGet"),
+            domain: domain.to_owned(),
+            parameters: vec![Parameter { name: String::from("timeout"), value: String::from("30") }],
+            tags: vec![String::from("top-sites")],
+            schedule: None,
+            start_timestamp: None,
+            end_timestamp: None,
+        }
+    }
+
+    fn tag_filter() -> TagFilter {
+        TagFilter::new(vec![String::from("top-sites")], Vec::new())
+    }
+
+    //hashes of a single bucket holding the operations
+    fn bucket_hashes(operations: &[Operation]) -> HashMap<u64, u64> {
+        let mut hashes = HashMap::new();
+        hashes.insert(0, proddle::bucket_digest(operations));
+        hashes
+    }
+
+    //hashes a vantage holding the operations computes for each bucket of the ring
+    fn ring_hashes(bucket_ring: &BucketRing, operations: &[Operation]) -> HashMap<u64, u64> {
+        let mut buckets: HashMap<u64, Vec<Operation>> = bucket_ring.keys().iter().map(|x| (*x, Vec::new())).collect();
+        for operation in operations.iter().filter(|x| !x.deleted) {
+            buckets.get_mut(&bucket_ring.domain_bucket_key(&operation.domain)).unwrap().push(operation.clone());
+        }
+
+        buckets.iter().map(|(bucket_key, operations)| (*bucket_key, proddle::bucket_digest(operations))).collect()
+    }
+
+    //skip the wait for the next change check
+    fn expire_check(index: &OperationIndex) {
+        index.state.lock().unwrap().last_check = None;
+    }
+
+    #[test]
+    fn operations_are_reloaded_only_when_changed() {
+        let store = CountingStore::new();
+        store.store.add_operation(operation("google.com"));
+        let index = OperationIndex::new();
+
+        index.update_operations(&store, bucket_hashes(&[]), None, &tag_filter()).unwrap();
+        assert_eq!(store.loads(), 1);
+
+        //unchanged operations are not read again, whether or not the check interval elapsed
+        index.update_operations(&store, bucket_hashes(&[]), None, &tag_filter()).unwrap();
+        expire_check(&index);
+        index.update_operations(&store, bucket_hashes(&[]), None, &tag_filter()).unwrap();
+        assert_eq!(store.loads(), 1);
+
+        store.store.add_operation(operation("example.com"));
+        expire_check(&index);
+        let (operations, _, version) = index.update_operations(&store, bucket_hashes(&[]), None, &tag_filter()).unwrap();
+        assert_eq!(store.loads(), 2);
+        assert_eq!(version, 2);
+        assert_eq!(operations[&0].len(), 2);
+    }
+
+    #[test]
+    fn changes_keeping_count_and_latest_version_are_reloaded() {
+        let store = CountingStore::new();
+        store.store.add_operation(operation("google.com"));
+        store.store.add_operation(operation("example.com"));
+        let index = OperationIndex::new();
+        index.update_operations(&store, bucket_hashes(&[]), None, &tag_filter()).unwrap();
+
+        //google.com is edited and assigned the latest version without raising it
+        let mut operations = store.store.operations().unwrap();
+        operations[0].version = 2;
+        operations[0].parameters.clear();
+        store.store.set_operations(operations.clone());
+        expire_check(&index);
+
+        let (full_operations, _, version) = index.update_operations(&store, bucket_hashes(&[]), None, &tag_filter()).unwrap();
+        assert_eq!(store.loads(), 2);
+        assert_eq!(version, 2);
+        assert_eq!(proddle::bucket_digest(&full_operations[&0]), proddle::bucket_digest(&operations));
+    }
+
+    #[test]
+    fn buckets_are_cached_per_bucket_count_and_tag_filter() {
+        let store = MemoryStore::new();
+        store.add_operation(operation("google.com"));
+        let index = OperationIndex::new();
+        let cached = |bucket_count: u64, tag_filter: &TagFilter| {
+            index.state.lock().unwrap().buckets.get(&(bucket_count, tag_filter.clone())).cloned().unwrap()
+        };
+
+        index.update_operations(&store, bucket_hashes(&[]), None, &tag_filter()).unwrap();
+        let operation_buckets = cached(1, &tag_filter());
+        index.update_operations(&store, bucket_hashes(&[]), None, &tag_filter()).unwrap();
+        assert!(Arc::ptr_eq(&operation_buckets, &cached(1, &tag_filter())));
+
+        //another ring or filter is bucketed separately
+        let other_filter = TagFilter::new(vec![String::from("other")], Vec::new());
+        let (operations, _, _) = index.update_operations(&store, bucket_hashes(&[]), None, &other_filter).unwrap();
+        assert!(operations.is_empty());
+        index.update_operations(&store, ring_hashes(&BucketRing::new(4).unwrap(), &[]), None, &tag_filter()).unwrap();
+        assert_eq!(index.state.lock().unwrap().buckets.len(), 3);
+        assert!(Arc::ptr_eq(&operation_buckets, &cached(1, &tag_filter())));
+
+        //a reload discards the cached buckets
+        store.add_operation(operation("example.com"));
+        expire_check(&index);
+        index.update_operations(&store, bucket_hashes(&[]), None, &tag_filter()).unwrap();
+        assert_eq!(index.state.lock().unwrap().buckets.len(), 1);
+        assert!(!Arc::ptr_eq(&operation_buckets, &cached(1, &tag_filter())));
+    }
+
+    #[test]
+    fn changes_are_sent_as_deltas_and_diverged_buckets_in_full() {
+        let bucket_ring = BucketRing::new(4).unwrap();
+        let (google, github, amazon) = ("google.com", "github.com", "amazon.com");
+        let (google_key, github_key, amazon_key) = (bucket_ring.domain_bucket_key(google), bucket_ring.domain_bucket_key(github),
+            bucket_ring.domain_bucket_key(amazon));
+        assert!(google_key != github_key && google_key != amazon_key && github_key != amazon_key);
+
+        let store = MemoryStore::new();
+        store.add_operation(operation(google));
+        store.add_operation(operation(github));
+        store.add_operation(operation(amazon));
+        let synchronized = store.operations().unwrap();
+
+        //a vantage which has not synchronized gets every non-empty bucket in full
+        let (operations, operation_delta, version) = OperationIndex::new()
+            .update_operations(&store, ring_hashes(&bucket_ring, &[]), None, &tag_filter()).unwrap();
+        assert!(operation_delta.is_none());
+        assert_eq!(version, 3);
+        assert_eq!(operations.len(), 3);
+        assert_eq!(operations[&google_key][0].domain, google);
+
+        //google.com is deleted and github.com no longer selected
+        store.delete_operation("HttpGet", google);
+        let mut unselected = operation(github);
+        unselected.tags = vec![String::from("other")];
+        store.add_operation(unselected);
+        let index = OperationIndex::new();
+
+        let (operations, operation_delta, version) = index
+            .update_operations(&store, ring_hashes(&bucket_ring, &synchronized), Some(3), &tag_filter()).unwrap();
+        assert_eq!(version, 5);
+        assert!(operations.is_empty());
+        let operation_delta = operation_delta.unwrap();
+        assert_eq!(operation_delta.operations.len(), 2);
+        assert!(operation_delta.operations[&google_key][0].deleted);
+        assert!(operation_delta.operations[&github_key][0].deleted);
+        let empty_hash = proddle::bucket_digest(&Vec::new());
+        assert_eq!(operation_delta.operation_bucket_hashes[&google_key], empty_hash);
+        assert_eq!(operation_delta.operation_bucket_hashes[&github_key], empty_hash);
+
+        //a diverged bucket outside the delta is sent in full
+        let mut diverged = ring_hashes(&bucket_ring, &synchronized);
+        diverged.insert(amazon_key, empty_hash);
+        let (operations, operation_delta, _) = index.update_operations(&store, diverged, Some(3), &tag_filter()).unwrap();
+        assert_eq!(operations.keys().collect::<Vec<_>>(), vec![&amazon_key]);
+        assert_eq!(operations[&amazon_key][0].domain, amazon);
+        assert_eq!(operation_delta.unwrap().operations.len(), 2);
+
+        //a vantage ahead of the bridge gets its diverged buckets in full instead of a delta
+        let (operations, operation_delta, _) = index
+            .update_operations(&store, ring_hashes(&bucket_ring, &synchronized), Some(6), &tag_filter()).unwrap();
+        assert!(operation_delta.is_none());
+        assert_eq!(operations.len(), 2);
+        assert!(operations[&google_key].is_empty());
+        assert!(operations[&github_key].is_empty());
+    }
+
+    #[test]
+    fn vantages_behind_purged_tombstones_recover_by_bucket_hashes() {
+        let store = MemoryStore::new();
+        store.add_operation(operation("google.com"));
+        store.add_operation(operation("example.com"));
+        let synchronized = store.operations().unwrap();
+
+        store.delete_operation("HttpGet", "google.com");
+        assert_eq!(store.purge_tombstones(3), 1);
+        assert_eq!(store.operations_version().unwrap(), OperationsVersion { count: 1, version: 3, version_sum: 2 });
+
+        //the vantage never saw the tombstone so the bucket is sent in full
+        let (operations, operation_delta, version) = OperationIndex::new()
+            .update_operations(&store, bucket_hashes(&synchronized), Some(2), &tag_filter()).unwrap();
+        assert!(operation_delta.is_none());
+        assert_eq!(version, 3);
+        assert_eq!(operations[&0].iter().map(|x| x.domain.as_str()).collect::<Vec<_>>(), vec!["example.com"]);
+
+        //a vantage which synchronized the tombstone still gets deltas
+        let remaining = store.operations().unwrap();
+        let (operations, operation_delta, _) = OperationIndex::new()
+            .update_operations(&store, bucket_hashes(&remaining), Some(3), &tag_filter()).unwrap();
+        assert!(operations.is_empty());
+        assert!(operation_delta.unwrap().operations.is_empty());
+
+        //versions continue after the purged tombstone
+        store.add_operation(operation("wikipedia.org"));
+        assert_eq!(store.operations_version().unwrap(), OperationsVersion { count: 2, version: 4, version_sum: 6 });
+    }
+}
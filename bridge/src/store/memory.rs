@@ -0,0 +1,179 @@
+use bson::Document;
+use proddle::{ClockSample, Heartbeat, Operation, ProddleError, VantageConfig};
+
+use store::{OperationsVersion, Store};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Latest heartbeat of a vantage held by the memory store.
+#[derive(Clone, Debug)]
+pub struct VantageRecord {
+    pub ip_address: String,
+    pub heartbeat: Heartbeat,
+    pub clock_offset_milliseconds: Option<i64>,
+    pub first_heartbeat: i64,
+    pub last_heartbeat: i64,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    operations: Vec<Operation>,
+    purged_version: i64,
+    vantage_configs: Vec<VantageConfig>,
+    measurements: Vec<Document>,
+    measurement_insert_failures: usize,
+    statistics: Vec<Document>,
+    vantages: HashMap<String, VantageRecord>,
+}
+
+/// Store keeping everything in memory for tests and trial runs. Clones share the same data so
+/// a handle may be kept to inspect and modify the store while the bridge is running.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    data: Arc<Mutex<MemoryData>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Adds an operation, replacing any with the same measurement class and domain.
+    pub fn add_operation(&self, mut operation: Operation) {
+        let mut data = self.data.lock().unwrap();
+        operation.version = next_version(&data);
+        operation.deleted = false;
+        data.operations.retain(|x| x.measurement_class != operation.measurement_class || x.domain != operation.domain);
+        data.operations.push(operation);
+    }
+
+    /// Replaces the operation with a tombstone, returning false if no such operation exists.
+    pub fn delete_operation(&self, measurement_class: &str, domain: &str) -> bool {
+        let mut data = self.data.lock().unwrap();
+        let version = next_version(&data);
+        match data.operations.iter_mut().find(|x| x.measurement_class == measurement_class && x.domain == domain && !x.deleted) {
+            Some(operation) => {
+                operation.version = version;
+                operation.deleted = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Replaces every operation with those given, keeping their versions as set. Other changes
+    /// assign new versions, this allows edits which do not.
+    pub fn set_operations(&self, operations: Vec<Operation>) {
+        self.data.lock().unwrap().operations = operations;
+    }
+
+    /// Removes tombstones with versions up to 'version', returning the number removed.
+    pub fn purge_tombstones(&self, version: i64) -> usize {
+        let mut data = self.data.lock().unwrap();
+        let count = data.operations.len();
+        data.operations.retain(|x| !x.deleted || x.version > version);
+        if data.operations.len() != count && version > data.purged_version {
+            data.purged_version = version;
+        }
+
+        count - data.operations.len()
+    }
+
+    /// Adds a vantage configuration, replacing any with the same hostname and group.
+    pub fn set_vantage_config(&self, vantage_config: VantageConfig) {
+        let mut data = self.data.lock().unwrap();
+        data.vantage_configs.retain(|x| x.hostname != vantage_config.hostname || x.group != vantage_config.group);
+        data.vantage_configs.push(vantage_config);
+    }
+
+    /// Removes the vantage configuration with the hostname and group, returning false if none exists.
+    pub fn delete_vantage_config(&self, hostname: Option<&str>, group: Option<&str>) -> bool {
+        let mut data = self.data.lock().unwrap();
+        let count = data.vantage_configs.len();
+        data.vantage_configs.retain(|x| x.hostname.as_ref().map(|x| x.as_str()) != hostname || x.group.as_ref().map(|x| x.as_str()) != group);
+        data.vantage_configs.len() != count
+    }
+
+    /// Reports the next 'count' measurements inserted as failed without storing them.
+    pub fn fail_measurement_inserts(&self, count: usize) {
+        self.data.lock().unwrap().measurement_insert_failures = count;
+    }
+
+    pub fn measurements(&self) -> Vec<Document> {
+        self.data.lock().unwrap().measurements.clone()
+    }
+
+    pub fn statistics(&self) -> Vec<Document> {
+        self.data.lock().unwrap().statistics.clone()
+    }
+
+    pub fn vantages(&self) -> HashMap<String, VantageRecord> {
+        self.data.lock().unwrap().vantages.clone()
+    }
+}
+
+impl Store for MemoryStore {
+    fn operations(&self) -> Result<Vec<Operation>, ProddleError> {
+        Ok(self.data.lock().unwrap().operations.clone())
+    }
+
+    fn operations_version(&self) -> Result<OperationsVersion, ProddleError> {
+        let data = self.data.lock().unwrap();
+        Ok(
+            OperationsVersion {
+                count: data.operations.len() as u64,
+                version: next_version(&data) - 1,
+                version_sum: data.operations.iter().fold(0, |sum, x| sum.wrapping_add(x.version)),
+            }
+        )
+    }
+
+    fn purged_version(&self) -> Result<i64, ProddleError> {
+        Ok(self.data.lock().unwrap().purged_version)
+    }
+
+    fn insert_measurements(&self, measurements: Vec<Document>) -> Result<Vec<usize>, ProddleError> {
+        let mut data = self.data.lock().unwrap();
+        let mut measurement_failures = Vec::new();
+        for (i, measurement) in measurements.into_iter().enumerate() {
+            if data.measurement_insert_failures > 0 {
+                data.measurement_insert_failures -= 1;
+                measurement_failures.push(i);
+            } else {
+                data.measurements.push(measurement);
+            }
+        }
+
+        Ok(measurement_failures)
+    }
+
+    fn insert_statistics(&self, statistics: Document) -> Result<(), ProddleError> {
+        self.data.lock().unwrap().statistics.push(statistics);
+        Ok(())
+    }
+
+    fn vantage_configs(&self) -> Result<Vec<VantageConfig>, ProddleError> {
+        Ok(self.data.lock().unwrap().vantage_configs.clone())
+    }
+
+    fn send_heartbeat(&self, vantage_id: &str, ip_address: &str, heartbeat: &Heartbeat, clock_sample: Option<&ClockSample>,
+                      timestamp: i64) -> Result<(), ProddleError> {
+        let mut data = self.data.lock().unwrap();
+        let first_heartbeat = data.vantages.get(vantage_id).map(|x| x.first_heartbeat).unwrap_or(timestamp);
+        data.vantages.insert(vantage_id.to_owned(), VantageRecord {
+            ip_address: ip_address.to_owned(),
+            heartbeat: heartbeat.clone(),
+            clock_offset_milliseconds: clock_sample.map(|x| x.offset()),
+            first_heartbeat: first_heartbeat,
+            last_heartbeat: timestamp,
+        });
+
+        Ok(())
+    }
+}
+
+//versions continue after those of purged tombstones
+fn next_version(data: &MemoryData) -> i64 {
+    data.operations.iter().map(|x| x.version).chain(Some(data.purged_version)).max().unwrap_or(0) + 1
+}
@@ -0,0 +1,140 @@
+use bson::{self, Bson, Document};
+use proddle::{ClockSample, Heartbeat, Operation, ProddleError, VantageConfig};
+
+mod index;
+mod memory;
+mod mongo;
+mod sqlite;
+pub use self::index::OperationIndex;
+pub use self::memory::{MemoryStore, VantageRecord};
+pub use self::mongo::MongoStore;
+pub use self::sqlite::SqliteStore;
+
+use std::io::Cursor;
+
+/// Summary of the stored operations, compared to detect changes without reading them all. The
+/// version sum changes whenever any operation is assigned a new version, even if neither the
+/// count nor the highest version does, ex. two operations deleted concurrently with one version.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OperationsVersion {
+    pub count: u64,
+    pub version: i64,
+    pub version_sum: i64,
+}
+
+/// Storage backend of the bridge. Implementations provide the primitive reads and writes while
+/// configuration merging and measurement decoding are shared, operations are bucketed by the
+/// operation index.
+pub trait Store: Send + Sync {
+    /// Returns every operation, including the tombstones of deleted operations.
+    fn operations(&self) -> Result<Vec<Operation>, ProddleError>;
+
+    /// Inserts measurements, returning the indices of those which failed to insert.
+    fn insert_measurements(&self, measurements: Vec<Document>) -> Result<Vec<usize>, ProddleError>;
+
+    fn insert_statistics(&self, statistics: Document) -> Result<(), ProddleError>;
+
+    fn vantage_configs(&self) -> Result<Vec<VantageConfig>, ProddleError>;
+
+    /// Returns the number of operations, tombstones included, with the highest and the sum of
+    /// their versions.
+    fn operations_version(&self) -> Result<OperationsVersion, ProddleError>;
+
+    /// Returns the version up to which tombstones may have been purged, 0 if none were. Vantages
+    /// synchronized to an earlier version may hold purged operations and are not sent a delta.
+    fn purged_version(&self) -> Result<i64, ProddleError>;
+
+    /// Records the latest heartbeat of a vantage, one record per vantage id. The timestamp is
+    /// the bridge time the heartbeat was received in seconds.
+    fn send_heartbeat(&self, vantage_id: &str, ip_address: &str, heartbeat: &Heartbeat, clock_sample: Option<&ClockSample>,
+                      timestamp: i64) -> Result<(), ProddleError>;
+
+    /// Inserts measurements, adding clock corrected copies of their timestamps when the clock
+    /// offset of the vantage is known.
+    fn send_measurements(&self, measurements: Vec<Vec<u8>>, clock_offset: Option<i64>) -> Result<Vec<usize>, ProddleError> {
+        let mut measurement_failures = Vec::new();
+        let mut indices = Vec::new();
+        let mut documents = Vec::new();
+        for (i, measurement) in measurements.iter().enumerate() {
+            //parse as document
+            let mut cursor = Cursor::new(measurement);
+            match bson::decode_document(&mut cursor) {
+                Ok(mut document) => {
+                    if let Some(clock_offset) = clock_offset {
+                        correct_timestamps(&mut document, clock_offset);
+                    }
+
+                    indices.push(i);
+                    documents.push(document);
+                },
+                Err(e) => {
+                    error!("failed to decode measurement: {}", e);
+                    measurement_failures.push(i);
+                }
+            }
+        }
+
+        for index in try!(self.insert_measurements(documents)) {
+            measurement_failures.push(indices[index]);
+        }
+
+        measurement_failures.sort();
+        Ok(measurement_failures)
+    }
+
+    fn send_statistics(&self, statistics: Vec<u8>) -> Result<(), ProddleError> {
+        //parse as document and insert
+        let mut cursor = Cursor::new(statistics);
+        let document = try!(bson::decode_document(&mut cursor));
+        self.insert_statistics(document)
+    }
+
+    /// Merges the configurations of each group, in the order the vantage lists them, followed by
+    /// the vantage's own configuration. The timestamp of the merged configuration is its content
+    /// version, 0 indicates no setting applies.
+    fn get_vantage_config(&self, hostname: &str, groups: &Vec<String>) -> Result<VantageConfig, ProddleError> {
+        let mut group_configs: Vec<Option<VantageConfig>> = groups.iter().map(|_| None).collect();
+        let mut hostname_config = None;
+        for vantage_config in try!(self.vantage_configs()) {
+            if vantage_config.hostname.as_ref().map_or(false, |x| x == hostname) {
+                hostname_config = Some(vantage_config);
+            } else if let Some(index) = vantage_config.group.as_ref().and_then(|x| groups.iter().position(|group| group == x)) {
+                group_configs[index] = Some(vantage_config);
+            }
+        }
+
+        let mut merged_config = VantageConfig::default();
+        merged_config.hostname = Some(hostname.to_owned());
+        for vantage_config in group_configs.iter().chain(Some(&hostname_config)).filter_map(|x| x.as_ref()) {
+            merged_config.merge(vantage_config);
+        }
+
+        merged_config.timestamp = merged_config.content_version();
+        Ok(merged_config)
+    }
+}
+
+//keep the raw vantage clock timestamps and add copies shifted by the estimated clock offset
+fn correct_timestamps(document: &mut Document, clock_offset: i64) {
+    for key in ["start_timestamp_milliseconds", "end_timestamp_milliseconds"].iter() {
+        let timestamp = match document.get(key) {
+            Some(&Bson::I64(timestamp)) => Some(timestamp),
+            _ => None,
+        };
+
+        if let Some(timestamp) = timestamp {
+            document.insert_bson(format!("corrected_{}", key), Bson::I64(timestamp + clock_offset));
+        }
+    }
+
+    let corrected_timestamp = match document.get("corrected_start_timestamp_milliseconds") {
+        Some(&Bson::I64(timestamp)) => Some(timestamp / 1000),
+        _ => None,
+    };
+
+    if let Some(corrected_timestamp) = corrected_timestamp {
+        document.insert_bson(String::from("corrected_timestamp"), Bson::I64(corrected_timestamp));
+    }
+
+    document.insert_bson(String::from("clock_offset_milliseconds"), Bson::I64(clock_offset));
+}
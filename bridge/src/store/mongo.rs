@@ -0,0 +1,344 @@
+use bson::{self, Bson, Document};
+use mongodb::{Client, ClientOptions, ThreadedClient};
+use mongodb::coll::options::{InsertManyOptions, UpdateOptions};
+use mongodb::db::{Database, ThreadedDatabase};
+use proddle::{ClockSample, Heartbeat, Operation, ProddleError, VantageConfig};
+
+use store::{OperationsVersion, Store};
+
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+//seconds a connection may go unchecked before it is verified again
+static HEALTH_CHECK_INTERVAL_SECONDS: u64 = 30;
+//largest number of documents mongodb accepts in a single write
+static INSERT_BATCH_SIZE: usize = 1000;
+
+struct Connection {
+    client: Client,
+    db: Database,
+    last_health_check: Instant,
+}
+
+/// Store backed by MongoDB. A single authenticated client, which pools connections internally,
+/// is shared by every handler thread and replaced when it fails.
+pub struct MongoStore {
+    ip_address: String,
+    port: u16,
+    username: String,
+    password: String,
+    ca_file: String,
+    certificate_file: String,
+    key_file: String,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl MongoStore {
+    pub fn new(ip_address: &str, port: u16, username: &str, password: &str, ca_file: &str,
+               certificate_file: &str, key_file: &str) -> Result<MongoStore, ProddleError> {
+        Ok(
+            MongoStore {
+                ip_address: ip_address.to_owned(),
+                port: port,
+                username: username.to_owned(),
+                password: password.to_owned(),
+                ca_file: ca_file.to_owned(),
+                certificate_file: certificate_file.to_owned(),
+                key_file: key_file.to_owned(),
+                connection: Mutex::new(None),
+            }
+        )
+    }
+
+    fn open_connection(&self) -> Result<Connection, ProddleError> {
+        let client = if self.ca_file.eq("") && self.certificate_file.eq("") && self.key_file.eq("") {
+            try!(Client::connect(&self.ip_address, self.port))
+        } else {
+            let client_options = ClientOptions::with_ssl(&self.ca_file, &self.certificate_file, &self.key_file, true);
+            try!(Client::connect_with_options(&self.ip_address, self.port, client_options))
+        };
+
+        let db = client.db("proddle");
+        try!(db.auth(&self.username, &self.password));
+        Ok(
+            Connection {
+                client: client,
+                db: db,
+                last_health_check: Instant::now(),
+            }
+        )
+    }
+
+    //return the shared database, connecting if there is none and checking a connection which
+    //has not been checked recently is still alive
+    fn database(&self) -> Result<Database, ProddleError> {
+        let mut connection = self.connection.lock().unwrap();
+        let healthy = match *connection {
+            Some(ref mut connection) if connection.last_health_check.elapsed() < Duration::from_secs(HEALTH_CHECK_INTERVAL_SECONDS) => true,
+            Some(ref mut connection) => match connection.client.is_master() {
+                Ok(_) => {
+                    connection.last_health_check = Instant::now();
+                    true
+                },
+                Err(e) => {
+                    warn!("mongodb health check failed, reconnecting: {}", e);
+                    false
+                },
+            },
+            None => false,
+        };
+
+        if !healthy {
+            *connection = None;
+            *connection = Some(try!(self.open_connection()));
+        }
+
+        Ok(connection.as_ref().unwrap().db.clone())
+    }
+
+    //run an operation on the shared database, dropping the connection if mongodb fails so the
+    //next operation reconnects
+    fn with_database<T, F>(&self, f: F) -> Result<T, ProddleError>
+            where F: FnOnce(&Database) -> Result<T, ProddleError> {
+        let db = try!(self.database());
+        let result = f(&db);
+        if let Err(ProddleError::MongoDB(ref e)) = result {
+            warn!("mongodb operation failed, reconnecting on next use: {}", e);
+            self.reset_connection();
+        }
+
+        result
+    }
+
+    //drop the shared connection so the next operation reconnects
+    fn reset_connection(&self) {
+        *self.connection.lock().unwrap() = None;
+    }
+}
+
+impl Store for MongoStore {
+    fn operations(&self) -> Result<Vec<Operation>, ProddleError> {
+        self.with_database(|db| {
+            //parse mongodb documents into operations
+            let mut operations = Vec::new();
+            let cursor = try!(db.collection("operations").find(None, None));
+            for document in cursor {
+                let document = try!(document);
+                operations.push(try!(bson::from_bson(Bson::Document(document))));
+            }
+
+            Ok(operations)
+        })
+    }
+
+    fn operations_version(&self) -> Result<OperationsVersion, ProddleError> {
+        self.with_database(|db| {
+            //operations written before versioning have no version and count as version 0
+            let group = doc!("$group" => Bson::Document(doc!(
+                "_id" => Bson::Null,
+                "count" => Bson::Document(doc!("$sum" => 1)),
+                "version" => Bson::Document(doc!("$max" => "$version")),
+                "version_sum" => Bson::Document(doc!("$sum" => "$version"))
+            )));
+
+            //an empty collection produces no group
+            let mut cursor = try!(db.collection("operations").aggregate(vec![group], None));
+            match cursor.next() {
+                Some(document) => {
+                    let document = try!(document);
+                    Ok(
+                        OperationsVersion {
+                            count: get_integer(&document, "count") as u64,
+                            version: get_integer(&document, "version"),
+                            version_sum: get_integer(&document, "version_sum"),
+                        }
+                    )
+                },
+                None => Ok(OperationsVersion::default()),
+            }
+        })
+    }
+
+    fn purged_version(&self) -> Result<i64, ProddleError> {
+        self.with_database(|db| {
+            //written by yogi before it purges tombstones
+            match try!(db.collection("counters").find_one(Some(doc!("_id" => "purged_operation_version")), None)) {
+                Some(document) => Ok(document.get_i64("value").unwrap_or(0)),
+                None => Ok(0),
+            }
+        })
+    }
+
+    fn insert_measurements(&self, measurements: Vec<Document>) -> Result<Vec<usize>, ProddleError> {
+        self.with_database(|db| {
+            let collection = db.collection("measurements");
+            Ok(insert_batches(measurements, INSERT_BATCH_SIZE, |batch| {
+                //unordered so one failed document does not stop the rest of the batch
+                let batch_size = batch.len();
+                let mut insert_options = InsertManyOptions::new();
+                insert_options.ordered = Some(false);
+                let result = match collection.insert_many(batch, Some(insert_options)) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        self.reset_connection();
+                        return Err(e);
+                    },
+                };
+
+                //write errors carry the index of the document within the batch
+                let mut batch_failures = Vec::new();
+                if let Some(bulk_write_exception) = result.bulk_write_exception {
+                    for write_error in bulk_write_exception.write_errors {
+                        error!("failed to insert measurement: {}", write_error.message);
+                        batch_failures.push(write_error.index as usize);
+                    }
+
+                    //the documents were written but not acknowledged by the requested write concern
+                    if let Some(write_concern_error) = bulk_write_exception.write_concern_error {
+                        error!("write concern error inserting {} measurement(s): {}", batch_size, write_concern_error.message);
+                    }
+                }
+
+                Ok(batch_failures)
+            }))
+        })
+    }
+
+    fn insert_statistics(&self, statistics: Document) -> Result<(), ProddleError> {
+        self.with_database(|db| {
+            try!(db.collection("statistics").insert_one(statistics, None));
+            Ok(())
+        })
+    }
+
+    fn vantage_configs(&self) -> Result<Vec<VantageConfig>, ProddleError> {
+        self.with_database(|db| {
+            let mut vantage_configs = Vec::new();
+            let cursor = try!(db.collection("vantage_configs").find(None, None));
+            for document in cursor {
+                let document = try!(document);
+                vantage_configs.push(try!(bson::from_bson(Bson::Document(document))));
+            }
+
+            Ok(vantage_configs)
+        })
+    }
+
+    fn send_heartbeat(&self, vantage_id: &str, ip_address: &str, heartbeat: &Heartbeat, clock_sample: Option<&ClockSample>,
+                      timestamp: i64) -> Result<(), ProddleError> {
+        self.with_database(|db| {
+            let (clock_offset_milliseconds, round_trip_milliseconds) = match clock_sample {
+                Some(clock_sample) => (Bson::I64(clock_sample.offset()), Bson::I64(clock_sample.round_trip())),
+                None => (Bson::Null, Bson::Null),
+            };
+
+            let set_document = doc!(
+                "hostname" => heartbeat.vantage_hostname.to_owned(),
+                "ip_address" => ip_address.to_owned(),
+                "version" => heartbeat.version.to_owned(),
+                "uptime_seconds" => heartbeat.uptime_seconds,
+                "scheduled_operations" => heartbeat.scheduled_operations as i64,
+                "queue_depth" => heartbeat.queue_depth as i64,
+                "in_flight" => heartbeat.in_flight as i64,
+                "measurement_buffer_depth" => heartbeat.measurement_buffer_depth as i64,
+                "clock_offset_milliseconds" => clock_offset_milliseconds,
+                "round_trip_milliseconds" => round_trip_milliseconds,
+                "last_heartbeat" => timestamp
+            );
+
+            let mut update_options = UpdateOptions::new();
+            update_options.upsert = Some(true);
+            try!(db.collection("vantages").update_one(doc!("vantage_id" => vantage_id.to_owned()),
+                doc!("$set" => Bson::Document(set_document), "$setOnInsert" => Bson::Document(doc!("first_heartbeat" => timestamp))),
+                Some(update_options)));
+            Ok(())
+        })
+    }
+}
+
+//aggregation results keep the integer width of their inputs
+fn get_integer(document: &Document, key: &str) -> i64 {
+    match document.get(key) {
+        Some(&Bson::I32(value)) => value as i64,
+        Some(&Bson::I64(value)) => value,
+        _ => 0,
+    }
+}
+
+//inserts measurements in batches, mapping the failed indices reported within each batch to indices
+//of 'measurements'. a failed batch and every later batch are reported as failed so the batches
+//already inserted are not resent
+fn insert_batches<F, E>(measurements: Vec<Document>, batch_size: usize, mut insert_batch: F) -> Vec<usize>
+        where F: FnMut(Vec<Document>) -> Result<Vec<usize>, E>, E: Display {
+    let mut measurement_failures = Vec::new();
+    let measurement_count = measurements.len();
+    let mut offset = 0;
+    let mut measurements = measurements.into_iter().peekable();
+    while measurements.peek().is_some() {
+        let batch: Vec<Document> = measurements.by_ref().take(batch_size).collect();
+        let batch_len = batch.len();
+        match insert_batch(batch) {
+            Ok(batch_failures) => measurement_failures.extend(batch_failures.into_iter().map(|x| offset + x)),
+            Err(e) => {
+                error!("failed to insert {} measurement(s): {}", measurement_count - offset, e);
+                measurement_failures.extend(offset..measurement_count);
+                break;
+            },
+        }
+
+        offset += batch_len;
+    }
+
+    measurement_failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurements(count: i32) -> Vec<Document> {
+        (0..count).map(|x| doc!("index" => x)).collect()
+    }
+
+    #[test]
+    fn batch_failures_are_offset_to_request_indices() {
+        let mut batches = Vec::new();
+        let measurement_failures = insert_batches(measurements(7), 3, |batch| -> Result<Vec<usize>, String> {
+            batches.push(batch.iter().map(|x| x.get_i32("index").unwrap()).collect::<Vec<i32>>());
+            match batches.len() {
+                1 => Ok(vec![1]),
+                2 => Ok(vec![0, 2]),
+                _ => Ok(vec![0]),
+            }
+        });
+
+        assert_eq!(batches, vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]]);
+        assert_eq!(measurement_failures, vec![1, 3, 5, 6]);
+    }
+
+    #[test]
+    fn failed_batch_fails_the_remaining_measurements() {
+        let mut batch_count = 0;
+        let measurement_failures = insert_batches(measurements(8), 3, |_| {
+            batch_count += 1;
+            match batch_count {
+                1 => Ok(vec![2]),
+                _ => Err(String::from("connection reset")),
+            }
+        });
+
+        //the third batch is not attempted after the second fails
+        assert_eq!(batch_count, 2);
+        assert_eq!(measurement_failures, vec![2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn no_measurements_inserts_no_batches() {
+        let measurement_failures = insert_batches(Vec::new(), 3, |_| -> Result<Vec<usize>, String> {
+            panic!("no batch expected");
+        });
+
+        assert!(measurement_failures.is_empty());
+    }
+}
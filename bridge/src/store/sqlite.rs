@@ -0,0 +1,503 @@
+use bson::{self, Document};
+use proddle::{ClockSample, Heartbeat, Operation, Parameter, ProddleError, VantageConfig};
+use rusqlite::{self, Connection};
+use serde_json;
+
+use store::{OperationsVersion, Store};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+//tags and include tags are stored as json text, measurements and statistics keep the complete
+//bson document alongside the columns they are queried by. triggers assign operation versions
+//on every change, including changes to parameters and tags, and turn deletes into tombstones.
+//deleting a tombstone purges it and records its version, except the latest version which is
+//kept so versions continue to increase
+static SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS operations (
+        id INTEGER PRIMARY KEY,
+        version INTEGER NOT NULL DEFAULT 0,
+        deleted INTEGER NOT NULL DEFAULT 0,
+        timestamp INTEGER NOT NULL,
+        measurement_class TEXT NOT NULL,
+        domain TEXT NOT NULL,
+        schedule TEXT,
+        start_timestamp INTEGER,
+        end_timestamp INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS operation_parameters (
+        operation_id INTEGER NOT NULL REFERENCES operations(id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS operation_tags (
+        operation_id INTEGER NOT NULL REFERENCES operations(id) ON DELETE CASCADE,
+        tag TEXT NOT NULL
+    );
+    CREATE TRIGGER IF NOT EXISTS operations_insert_version AFTER INSERT ON operations BEGIN
+        UPDATE operations SET version = (SELECT MAX(version) FROM operations) + 1 WHERE id = NEW.id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS operations_update_version
+            AFTER UPDATE OF timestamp, measurement_class, domain, schedule, start_timestamp, end_timestamp, deleted ON operations BEGIN
+        UPDATE operations SET version = (SELECT MAX(version) FROM operations) + 1 WHERE id = NEW.id;
+    END;
+    DROP TRIGGER IF EXISTS operations_delete_tombstone;
+    CREATE TRIGGER operations_delete_tombstone BEFORE DELETE ON operations WHEN OLD.deleted = 0 BEGIN
+        UPDATE operations SET deleted = 1 WHERE id = OLD.id;
+        SELECT RAISE(IGNORE);
+    END;
+    CREATE TRIGGER IF NOT EXISTS operations_keep_latest_tombstone BEFORE DELETE ON operations
+            WHEN OLD.deleted = 1 AND OLD.version >= (SELECT MAX(version) FROM operations) BEGIN
+        SELECT RAISE(IGNORE);
+    END;
+    CREATE TABLE IF NOT EXISTS operations_purged (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        version INTEGER NOT NULL
+    );
+    CREATE TRIGGER IF NOT EXISTS operations_purge_tombstone AFTER DELETE ON operations BEGIN
+        INSERT OR REPLACE INTO operations_purged (id, version) VALUES (0, MAX(OLD.version, COALESCE((SELECT version FROM operations_purged), 0)));
+    END;
+    CREATE TRIGGER IF NOT EXISTS operation_parameters_insert_version AFTER INSERT ON operation_parameters BEGIN
+        UPDATE operations SET version = (SELECT MAX(version) FROM operations) + 1 WHERE id = NEW.operation_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS operation_parameters_update_version AFTER UPDATE ON operation_parameters BEGIN
+        UPDATE operations SET version = (SELECT MAX(version) FROM operations) + 1 WHERE id = NEW.operation_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS operation_parameters_delete_version AFTER DELETE ON operation_parameters BEGIN
+        UPDATE operations SET version = (SELECT MAX(version) FROM operations) + 1 WHERE id = OLD.operation_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS operation_tags_insert_version AFTER INSERT ON operation_tags BEGIN
+        UPDATE operations SET version = (SELECT MAX(version) FROM operations) + 1 WHERE id = NEW.operation_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS operation_tags_update_version AFTER UPDATE ON operation_tags BEGIN
+        UPDATE operations SET version = (SELECT MAX(version) FROM operations) + 1 WHERE id = NEW.operation_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS operation_tags_delete_version AFTER DELETE ON operation_tags BEGIN
+        UPDATE operations SET version = (SELECT MAX(version) FROM operations) + 1 WHERE id = OLD.operation_id;
+    END;
+    CREATE TABLE IF NOT EXISTS measurements (
+        id INTEGER PRIMARY KEY,
+        vantage_id TEXT,
+        vantage_hostname TEXT,
+        measurement_class TEXT,
+        measurement_domain TEXT,
+        timestamp INTEGER,
+        start_timestamp_milliseconds INTEGER,
+        end_timestamp_milliseconds INTEGER,
+        corrected_timestamp INTEGER,
+        clock_offset_milliseconds INTEGER,
+        error_category TEXT,
+        document BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS measurements_domain_timestamp ON measurements (measurement_domain, timestamp);
+    CREATE TABLE IF NOT EXISTS statistics (
+        id INTEGER PRIMARY KEY,
+        vantage_id TEXT,
+        vantage_hostname TEXT,
+        timestamp INTEGER,
+        document BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS vantage_configs (
+        hostname TEXT UNIQUE,
+        group_name TEXT UNIQUE,
+        timestamp INTEGER NOT NULL,
+        include_tags TEXT,
+        exclude_tags TEXT,
+        max_jitter_seconds INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS vantages (
+        vantage_id TEXT PRIMARY KEY,
+        hostname TEXT NOT NULL,
+        ip_address TEXT NOT NULL,
+        version TEXT NOT NULL,
+        uptime_seconds INTEGER NOT NULL,
+        scheduled_operations INTEGER NOT NULL,
+        queue_depth INTEGER NOT NULL,
+        in_flight INTEGER NOT NULL,
+        measurement_buffer_depth INTEGER NOT NULL,
+        clock_offset_milliseconds INTEGER,
+        round_trip_milliseconds INTEGER,
+        first_heartbeat INTEGER NOT NULL,
+        last_heartbeat INTEGER NOT NULL
+    );
+";
+
+/// Store backed by a single SQLite file, intended for small deployments and testing.
+/// Operations and vantage configurations are managed directly in the database file, deleting an
+/// operation marks it deleted so the deletion reaches vantages. Tombstones are purged by
+/// deleting them, ex. 'DELETE FROM operations WHERE deleted = 1 AND version <= 1000'.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn new(path: &str) -> Result<SqliteStore, ProddleError> {
+        let connection = try!(Connection::open(path).map_err(sqlite_error));
+        SqliteStore::from_connection(connection)
+    }
+
+    fn from_connection(connection: Connection) -> Result<SqliteStore, ProddleError> {
+        try!(connection.execute_batch("PRAGMA foreign_keys = ON;").map_err(sqlite_error));
+        try!(connection.execute_batch(SCHEMA).map_err(sqlite_error));
+
+        Ok(
+            SqliteStore {
+                connection: Mutex::new(connection),
+            }
+        )
+    }
+}
+
+impl Store for SqliteStore {
+    fn operations(&self) -> Result<Vec<Operation>, ProddleError> {
+        let connection = self.connection.lock().unwrap();
+
+        //parameters and tags are fetched once and attached to their operations
+        let mut parameters: HashMap<i64, Vec<Parameter>> = HashMap::new();
+        let mut statement = try!(connection.prepare("SELECT operation_id, name, value FROM operation_parameters ORDER BY rowid").map_err(sqlite_error));
+        let rows = try!(statement.query_map(&[], |row| (row.get::<i32, i64>(0), row.get::<i32, String>(1), row.get::<i32, String>(2))).map_err(sqlite_error));
+        for row in rows {
+            let (operation_id, name, value) = try!(row.map_err(sqlite_error));
+            parameters.entry(operation_id).or_insert(Vec::new()).push(Parameter {
+                name: name,
+                value: value,
+            });
+        }
+
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        let mut statement = try!(connection.prepare("SELECT operation_id, tag FROM operation_tags ORDER BY rowid").map_err(sqlite_error));
+        let rows = try!(statement.query_map(&[], |row| (row.get::<i32, i64>(0), row.get::<i32, String>(1))).map_err(sqlite_error));
+        for row in rows {
+            let (operation_id, tag) = try!(row.map_err(sqlite_error));
+            tags.entry(operation_id).or_insert(Vec::new()).push(tag);
+        }
+
+        let mut operations = Vec::new();
+        let mut statement = try!(connection.prepare("SELECT id, version, deleted, timestamp, measurement_class, domain, schedule, start_timestamp, end_timestamp FROM operations ORDER BY id").map_err(sqlite_error));
+        let rows = try!(statement.query_map(&[], |row| {
+            let id: i64 = row.get(0);
+            (id, Operation {
+                version: row.get(1),
+                deleted: row.get(2),
+                timestamp: row.get(3),
+                measurement_class: row.get(4),
+                domain: row.get(5),
+                parameters: Vec::new(),
+                tags: Vec::new(),
+                schedule: row.get(6),
+                start_timestamp: row.get(7),
+                end_timestamp: row.get(8),
+            })
+        }).map_err(sqlite_error));
+
+        for row in rows {
+            let (id, mut operation) = try!(row.map_err(sqlite_error));
+            operation.parameters = parameters.remove(&id).unwrap_or(Vec::new());
+            operation.tags = tags.remove(&id).unwrap_or(Vec::new());
+            operations.push(operation);
+        }
+
+        Ok(operations)
+    }
+
+    fn operations_version(&self) -> Result<OperationsVersion, ProddleError> {
+        let connection = self.connection.lock().unwrap();
+        let (count, version, version_sum) = try!(connection.query_row("SELECT COUNT(*), COALESCE(MAX(version), 0), COALESCE(SUM(version), 0) FROM operations", &[],
+            |row| (row.get::<i32, i64>(0), row.get::<i32, i64>(1), row.get::<i32, i64>(2))).map_err(sqlite_error));
+        Ok(
+            OperationsVersion {
+                count: count as u64,
+                version: version,
+                version_sum: version_sum,
+            }
+        )
+    }
+
+    fn purged_version(&self) -> Result<i64, ProddleError> {
+        let connection = self.connection.lock().unwrap();
+        let version = try!(connection.query_row("SELECT COALESCE(MAX(version), 0) FROM operations_purged", &[],
+            |row| row.get::<i32, i64>(0)).map_err(sqlite_error));
+        Ok(version)
+    }
+
+    fn insert_measurements(&self, measurements: Vec<Document>) -> Result<Vec<usize>, ProddleError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = try!(connection.transaction().map_err(sqlite_error));
+
+        let mut measurement_failures = Vec::new();
+        for (i, measurement) in measurements.iter().enumerate() {
+            let mut encoded = Vec::new();
+            if let Err(e) = bson::encode_document(&mut encoded, measurement) {
+                error!("failed to encode measurement: {}", e);
+                measurement_failures.push(i);
+                continue;
+            }
+
+            let result = transaction.execute("INSERT INTO measurements (vantage_id, vantage_hostname, measurement_class, measurement_domain,
+                    timestamp, start_timestamp_milliseconds, end_timestamp_milliseconds, corrected_timestamp, clock_offset_milliseconds,
+                    error_category, document) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                &[&get_string(measurement, "vantage_id"), &get_string(measurement, "vantage_hostname"),
+                  &get_string(measurement, "measurement_class"), &get_string(measurement, "measurement_domain"),
+                  &get_i64(measurement, "timestamp"), &get_i64(measurement, "start_timestamp_milliseconds"),
+                  &get_i64(measurement, "end_timestamp_milliseconds"), &get_i64(measurement, "corrected_timestamp"),
+                  &get_i64(measurement, "clock_offset_milliseconds"), &get_string(measurement, "error_category"), &encoded]);
+
+            if let Err(e) = result {
+                error!("failed to insert measurement: {}", e);
+                measurement_failures.push(i);
+            }
+        }
+
+        try!(transaction.commit().map_err(sqlite_error));
+        Ok(measurement_failures)
+    }
+
+    fn insert_statistics(&self, statistics: Document) -> Result<(), ProddleError> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut encoded = Vec::new();
+        try!(bson::encode_document(&mut encoded, &statistics));
+        try!(connection.execute("INSERT INTO statistics (vantage_id, vantage_hostname, timestamp, document) VALUES (?1, ?2, ?3, ?4)",
+            &[&get_string(&statistics, "vantage_id"), &get_string(&statistics, "vantage_hostname"), &get_i64(&statistics, "timestamp"), &encoded]).map_err(sqlite_error));
+        Ok(())
+    }
+
+    fn vantage_configs(&self) -> Result<Vec<VantageConfig>, ProddleError> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut statement = try!(connection.prepare("SELECT hostname, group_name, timestamp, include_tags, exclude_tags, max_jitter_seconds FROM vantage_configs").map_err(sqlite_error));
+        let rows = try!(statement.query_map(&[], |row| {
+            (row.get::<i32, Option<String>>(0), row.get::<i32, Option<String>>(1), row.get::<i32, i64>(2),
+             row.get::<i32, Option<String>>(3), row.get::<i32, Option<String>>(4), row.get::<i32, Option<i64>>(5))
+        }).map_err(sqlite_error));
+
+        let mut vantage_configs = Vec::new();
+        for row in rows {
+            let (hostname, group, timestamp, include_tags, exclude_tags, max_jitter_seconds) = try!(row.map_err(sqlite_error));
+            vantage_configs.push(VantageConfig {
+                hostname: hostname,
+                group: group,
+                timestamp: timestamp,
+                include_tags: match include_tags {
+                    Some(include_tags) => Some(try!(serde_json::from_str(&include_tags).map_err(|e| format!("invalid include_tags '{}': {}", include_tags, e)))),
+                    None => None,
+                },
+                exclude_tags: match exclude_tags {
+                    Some(exclude_tags) => Some(try!(serde_json::from_str(&exclude_tags).map_err(|e| format!("invalid exclude_tags '{}': {}", exclude_tags, e)))),
+                    None => None,
+                },
+                max_jitter_seconds: max_jitter_seconds,
+            });
+        }
+
+        Ok(vantage_configs)
+    }
+
+    fn send_heartbeat(&self, vantage_id: &str, ip_address: &str, heartbeat: &Heartbeat, clock_sample: Option<&ClockSample>,
+                      timestamp: i64) -> Result<(), ProddleError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = try!(connection.transaction().map_err(sqlite_error));
+
+        let scheduled_operations = heartbeat.scheduled_operations as i64;
+        let queue_depth = heartbeat.queue_depth as i64;
+        let in_flight = heartbeat.in_flight as i64;
+        let measurement_buffer_depth = heartbeat.measurement_buffer_depth as i64;
+        let clock_offset_milliseconds = clock_sample.map(|x| x.offset());
+        let round_trip_milliseconds = clock_sample.map(|x| x.round_trip());
+
+        //first_heartbeat is only set when the vantage is inserted
+        try!(transaction.execute("INSERT OR IGNORE INTO vantages (vantage_id, hostname, ip_address, version, uptime_seconds, scheduled_operations,
+                queue_depth, in_flight, measurement_buffer_depth, first_heartbeat, last_heartbeat) VALUES (?1, '', '', '', 0, 0, 0, 0, 0, ?2, ?2)",
+            &[&vantage_id, &timestamp]).map_err(sqlite_error));
+        try!(transaction.execute("UPDATE vantages SET hostname = ?2, ip_address = ?3, version = ?4, uptime_seconds = ?5, scheduled_operations = ?6,
+                queue_depth = ?7, in_flight = ?8, measurement_buffer_depth = ?9, clock_offset_milliseconds = ?10, round_trip_milliseconds = ?11,
+                last_heartbeat = ?12 WHERE vantage_id = ?1",
+            &[&vantage_id, &heartbeat.vantage_hostname, &ip_address, &heartbeat.version, &heartbeat.uptime_seconds, &scheduled_operations,
+              &queue_depth, &in_flight, &measurement_buffer_depth, &clock_offset_milliseconds, &round_trip_milliseconds, &timestamp]).map_err(sqlite_error));
+
+        try!(transaction.commit().map_err(sqlite_error));
+        Ok(())
+    }
+}
+
+//rusqlite errors are converted here so the proddle crate does not depend on rusqlite
+fn sqlite_error(err: rusqlite::Error) -> ProddleError {
+    ProddleError::from(format!("sqlite error: {}", err))
+}
+
+fn get_string(document: &Document, key: &str) -> Option<String> {
+    document.get_str(key).ok().map(|x| x.to_owned())
+}
+
+fn get_i64(document: &Document, key: &str) -> Option<i64> {
+    document.get_i64(key).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> SqliteStore {
+        SqliteStore::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn execute(store: &SqliteStore, sql: &str) {
+        store.connection.lock().unwrap().execute_batch(sql).unwrap();
+    }
+
+    fn count_and_version(store: &SqliteStore) -> (u64, i64) {
+        let operations_version = store.operations_version().unwrap();
+        (operations_version.count, operations_version.version)
+    }
+
+    fn heartbeat(vantage_hostname: &str, scheduled_operations: u64) -> Heartbeat {
+        Heartbeat {
+            vantage_hostname: vantage_hostname.to_owned(),
+            version: String::from("0.3.1"),
+            uptime_seconds: 60,
+            scheduled_operations: scheduled_operations,
+            queue_depth: 1,
+            in_flight: 2,
+            measurement_buffer_depth: 3,
+        }
+    }
+
+    #[test]
+    fn schema_versions_changes_and_keeps_tombstones() {
+        let store = store();
+        execute(&store, SCHEMA);
+
+        execute(&store, "INSERT INTO operations (timestamp, measurement_class, domain) VALUES (100, 'HttpGet', 'google.com');
+            INSERT INTO operation_tags (operation_id, tag) VALUES (1, 'top-sites');");
+        assert_eq!(count_and_version(&store), (1, 2));
+
+        //deleting an operation leaves a tombstone with a new version
+        execute(&store, "INSERT INTO operations (timestamp, measurement_class, domain) VALUES (200, 'HttpGet', 'example.com');
+            DELETE FROM operations WHERE id = 1;");
+        assert_eq!(count_and_version(&store), (2, 4));
+
+        let operations = store.operations().unwrap();
+        assert_eq!(operations[0].domain, "google.com");
+        assert!(operations[0].deleted);
+        assert_eq!(operations[0].version, 4);
+        assert_eq!(operations[0].tags, vec![String::from("top-sites")]);
+        assert!(!operations[1].deleted);
+        assert_eq!(operations[1].version, 3);
+    }
+
+    #[test]
+    fn deleting_tombstones_purges_them() {
+        let store = store();
+        execute(&store, "INSERT INTO operations (timestamp, measurement_class, domain) VALUES (100, 'HttpGet', 'google.com');
+            INSERT INTO operation_tags (operation_id, tag) VALUES (1, 'top-sites');
+            INSERT INTO operations (timestamp, measurement_class, domain) VALUES (200, 'HttpGet', 'example.com');
+            INSERT INTO operations (timestamp, measurement_class, domain) VALUES (300, 'HttpGet', 'wikipedia.org');
+            DELETE FROM operations WHERE id IN (1, 3);");
+        assert_eq!(store.purged_version().unwrap(), 0);
+        assert_eq!(count_and_version(&store), (3, 6));
+
+        //the tombstone holding the latest version is kept
+        execute(&store, "DELETE FROM operations WHERE deleted = 1;");
+        assert_eq!(store.purged_version().unwrap(), 5);
+        assert_eq!(count_and_version(&store), (2, 6));
+        assert_eq!(store.operations_version().unwrap().version_sum, 9);
+
+        let operations = store.operations().unwrap();
+        assert_eq!(operations.iter().map(|x| (x.domain.as_str(), x.deleted)).collect::<Vec<_>>(),
+            vec![("example.com", false), ("wikipedia.org", true)]);
+        let tag_count: i64 = store.connection.lock().unwrap().query_row("SELECT COUNT(*) FROM operation_tags", &[], |row| row.get(0)).unwrap();
+        assert_eq!(tag_count, 0);
+
+        //later changes are versioned after the purged tombstones
+        execute(&store, "DELETE FROM operations WHERE id = 2;");
+        assert_eq!(count_and_version(&store), (2, 7));
+    }
+
+    #[test]
+    fn operations_are_reassembled_with_parameters_and_tags() {
+        let store = store();
+        execute(&store, "INSERT INTO operations (timestamp, measurement_class, domain, schedule, start_timestamp) VALUES (100, 'HttpGet', 'google.com', '@once', 50);
+            INSERT INTO operations (timestamp, measurement_class, domain) VALUES (200, 'Ping', 'example.com');
+            INSERT INTO operation_parameters (operation_id, name, value) VALUES (1, 'timeout', '30');
+            INSERT INTO operation_parameters (operation_id, name, value) VALUES (2, 'count', '4');
+            INSERT INTO operation_parameters (operation_id, name, value) VALUES (1, 'retries', '2');
+            INSERT INTO operation_tags (operation_id, tag) VALUES (2, 'icmp');
+            INSERT INTO operation_tags (operation_id, tag) VALUES (1, 'top-sites');
+            INSERT INTO operation_tags (operation_id, tag) VALUES (1, 'http');");
+
+        let operations = store.operations().unwrap();
+        assert_eq!(operations.len(), 2);
+
+        let google = &operations[0];
+        assert_eq!(google.domain, "google.com");
+        assert_eq!(google.measurement_class, "HttpGet");
+        assert_eq!(google.timestamp, 100);
+        assert_eq!(google.schedule, Some(String::from("@once")));
+        assert_eq!(google.start_timestamp, Some(50));
+        assert_eq!(google.end_timestamp, None);
+        assert_eq!(google.parameters.iter().map(|x| (x.name.as_str(), x.value.as_str())).collect::<Vec<_>>(),
+            vec![("timeout", "30"), ("retries", "2")]);
+        assert_eq!(google.tags, vec![String::from("top-sites"), String::from("http")]);
+
+        let example = &operations[1];
+        assert_eq!(example.domain, "example.com");
+        assert_eq!(example.schedule, None);
+        assert_eq!(example.parameters.iter().map(|x| (x.name.as_str(), x.value.as_str())).collect::<Vec<_>>(), vec![("count", "4")]);
+        assert_eq!(example.tags, vec![String::from("icmp")]);
+
+        //the last change to each operation sets its version
+        assert_eq!(count_and_version(&store), (2, 8));
+        assert_eq!(google.version, 8);
+        assert_eq!(example.version, 6);
+    }
+
+    #[test]
+    fn heartbeats_update_a_single_vantage_row() {
+        let store = store();
+        let clock_sample = ClockSample { originate: 0, receive: 60, transmit: 70, destination: 20 };
+        store.send_heartbeat("vantage-id", "10.0.0.1", &heartbeat("vantage-1", 5), Some(&clock_sample), 1000).unwrap();
+
+        let select = "SELECT COUNT(*), hostname, ip_address, scheduled_operations, clock_offset_milliseconds, round_trip_milliseconds,
+            first_heartbeat, last_heartbeat FROM vantages";
+        let vantage = |store: &SqliteStore| store.connection.lock().unwrap().query_row(select, &[], |row| {
+            (row.get::<i32, i64>(0), row.get::<i32, String>(1), row.get::<i32, String>(2), row.get::<i32, i64>(3),
+             row.get::<i32, Option<i64>>(4), row.get::<i32, Option<i64>>(5), row.get::<i32, i64>(6), row.get::<i32, i64>(7))
+        }).unwrap();
+
+        assert_eq!(vantage(&store), (1, String::from("vantage-1"), String::from("10.0.0.1"), 5, Some(55), Some(10), 1000, 1000));
+
+        //a later heartbeat replaces everything but the first heartbeat timestamp
+        store.send_heartbeat("vantage-id", "10.0.0.2", &heartbeat("vantage-2", 7), None, 2000).unwrap();
+        assert_eq!(vantage(&store), (1, String::from("vantage-2"), String::from("10.0.0.2"), 7, None, None, 1000, 2000));
+    }
+
+    #[test]
+    fn vantage_configs_decode_json_columns() {
+        let store = store();
+        execute(&store, "INSERT INTO vantage_configs (hostname, group_name, timestamp, include_tags, exclude_tags, max_jitter_seconds)
+                VALUES ('vantage-1', NULL, 10, '{\"top-sites\": 300}', '[\"slow\"]', 5);
+            INSERT INTO vantage_configs (hostname, group_name, timestamp) VALUES (NULL, 'group', 20);");
+
+        let mut vantage_configs = store.vantage_configs().unwrap();
+        vantage_configs.sort_by_key(|x| x.timestamp);
+        assert_eq!(vantage_configs.len(), 2);
+
+        let host = &vantage_configs[0];
+        assert_eq!(host.hostname, Some(String::from("vantage-1")));
+        assert_eq!(host.group, None);
+        assert_eq!(host.include_tags.as_ref().and_then(|x| x.get("top-sites")), Some(&300));
+        assert_eq!(host.include_tags.as_ref().map(|x| x.len()), Some(1));
+        assert_eq!(host.exclude_tags, Some(vec![String::from("slow")]));
+        assert_eq!(host.max_jitter_seconds, Some(5));
+
+        let group = &vantage_configs[1];
+        assert_eq!(group.hostname, None);
+        assert_eq!(group.group, Some(String::from("group")));
+        assert_eq!(group.include_tags, None);
+        assert_eq!(group.exclude_tags, None);
+        assert_eq!(group.max_jitter_seconds, None);
+
+        execute(&store, "UPDATE vantage_configs SET include_tags = '[' WHERE group_name = 'group';");
+        assert!(store.vantage_configs().is_err());
+    }
+}
@@ -2,12 +2,12 @@
 while true; do
     #start bridge
     ./target/debug/bridge -u proddle -p 'f@#d4k(YBN:c?u$gVh7W' &
+    BRIDGE_PID=$!
 
     sleep 7200
 
-    #stop bridge
+    #stop bridge, allowing open requests to complete
     echo "RESTARTING BRIDGE"
-    for PID in `ps -aux | grep bridge | awk '{print $2}'`; do
-        kill $PID
-    done
+    kill -TERM $BRIDGE_PID
+    wait $BRIDGE_PID
 done
@@ -0,0 +1,113 @@
+use ProddleError;
+use hash::hash_string;
+
+/// Division of the domain hash space into buckets shared by bridges and vantages. Bucket keys
+/// are the lowest hash of each bucket, 'k * (u64::max_value() / bucket_count)' for the k-th bucket, so
+/// the last bucket also holds the remainder of the hash space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BucketRing {
+    keys: Vec<u64>,
+}
+
+impl BucketRing {
+    pub fn new(bucket_count: u64) -> Result<BucketRing, ProddleError> {
+        if bucket_count == 0 {
+            return Err(ProddleError::from("bucket_count must be positive"));
+        }
+
+        let width = u64::max_value() / bucket_count;
+        Ok(
+            BucketRing {
+                keys: (0..bucket_count).map(|x| x * width).collect(),
+            }
+        )
+    }
+
+    /// Rebuilds the ring from the bucket keys sent by a vantage, failing unless they are exactly
+    /// the keys of a ring with as many buckets.
+    pub fn from_keys<'a, I: IntoIterator<Item=&'a u64>>(keys: I) -> Result<BucketRing, ProddleError> {
+        let mut keys: Vec<u64> = keys.into_iter().map(|x| *x).collect();
+        keys.sort();
+
+        let bucket_ring = try!(BucketRing::new(keys.len() as u64));
+        if bucket_ring.keys != keys {
+            return Err(ProddleError::from(format!("bucket keys do not match a ring of {} bucket(s)", keys.len())));
+        }
+
+        Ok(bucket_ring)
+    }
+
+    pub fn bucket_count(&self) -> u64 {
+        self.keys.len() as u64
+    }
+
+    pub fn keys(&self) -> &[u64] {
+        &self.keys
+    }
+
+    /// Returns the key of the bucket containing the hash.
+    pub fn bucket_key(&self, hash: u64) -> u64 {
+        match self.keys.binary_search(&hash) {
+            Ok(index) => self.keys[index],
+            Err(index) => self.keys[index - 1],
+        }
+    }
+
+    /// Returns the key of the bucket the domain's operations belong to.
+    pub fn domain_bucket_key(&self, domain: &str) -> u64 {
+        self.bucket_key(hash_string(domain))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_follow_the_wire_format() {
+        assert!(BucketRing::new(0).is_err());
+        assert_eq!(BucketRing::new(1).unwrap().keys(), &[0]);
+        assert_eq!(BucketRing::new(4).unwrap().keys(), &[0, 0x3fffffffffffffff, 0x7ffffffffffffffe, 0xbffffffffffffffd]);
+    }
+
+    #[test]
+    fn hashes_map_to_the_bucket_at_or_below_them() {
+        for bucket_count in [1, 2, 3, 4, 7, 1024].iter() {
+            let bucket_ring = BucketRing::new(*bucket_count).unwrap();
+            let keys = bucket_ring.keys();
+            assert_eq!(bucket_ring.bucket_key(0), 0);
+            for (i, key) in keys.iter().enumerate() {
+                assert_eq!(bucket_ring.bucket_key(*key), *key);
+                if i > 0 {
+                    assert_eq!(bucket_ring.bucket_key(*key - 1), keys[i - 1]);
+                }
+            }
+
+            //the last bucket holds the remainder of the hash space
+            let last_key = keys[keys.len() - 1];
+            assert_eq!(bucket_ring.bucket_key(*bucket_count * (u64::max_value() / *bucket_count)), last_key);
+            assert_eq!(bucket_ring.bucket_key(u64::max_value()), last_key);
+        }
+    }
+
+    #[test]
+    fn domains_map_to_the_bucket_of_their_hash() {
+        let bucket_ring = BucketRing::new(4).unwrap();
+        assert_eq!(bucket_ring.domain_bucket_key("google.com"), bucket_ring.bucket_key(hash_string("google.com")));
+    }
+
+    #[test]
+    fn from_keys_requires_the_keys_of_a_ring() {
+        let bucket_ring = BucketRing::new(4).unwrap();
+        let mut keys = bucket_ring.keys().to_vec();
+        keys.reverse();
+        assert_eq!(BucketRing::from_keys(&keys).unwrap(), bucket_ring);
+
+        //no keys, a partial ring, a duplicated key and keys of another division are rejected
+        assert!(BucketRing::from_keys(&Vec::new()).is_err());
+        assert!(BucketRing::from_keys(&bucket_ring.keys()[..3]).is_err());
+        assert!(BucketRing::from_keys(&[0, 0]).is_err());
+        assert!(BucketRing::from_keys(&[0, u64::max_value() / 2 + 1]).is_err());
+        assert!(BucketRing::from_keys(&[1]).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Timestamps in milliseconds of a single vantage request and bridge response, the originate
+/// and destination timestamps are taken on the vantage clock and the receive and transmit
+/// timestamps on the bridge clock.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClockSample {
+    pub originate: i64,
+    pub receive: i64,
+    pub transmit: i64,
+    pub destination: i64,
+}
+
+impl ClockSample {
+    /// Estimates the bridge clock minus the vantage clock, assuming symmetric network delay.
+    pub fn offset(&self) -> i64 {
+        ((self.receive - self.originate) + (self.transmit - self.destination)) / 2
+    }
+
+    /// Network delay of the exchange, excluding time spent processing on the bridge.
+    pub fn round_trip(&self) -> i64 {
+        (self.destination - self.originate) - (self.transmit - self.receive)
+    }
+}
+
+/// Returns the current unix time in milliseconds.
+pub fn timestamp_milliseconds() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64 * 1000 + duration.subsec_nanos() as i64 / 1_000_000,
+        Err(e) => -(e.duration().as_secs() as i64 * 1000 + e.duration().subsec_nanos() as i64 / 1_000_000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symmetric_delay_gives_exact_offset() {
+        //vantage clock 1000ms behind, 50ms each way and 20ms on the bridge
+        let clock_sample = ClockSample { originate: 0, receive: 1050, transmit: 1070, destination: 120 };
+        assert_eq!(clock_sample.offset(), 1000);
+        assert_eq!(clock_sample.round_trip(), 100);
+    }
+
+    #[test]
+    fn vantage_clock_ahead_gives_negative_offset() {
+        let clock_sample = ClockSample { originate: 5000, receive: 2010, transmit: 2015, destination: 5025 };
+        assert_eq!(clock_sample.offset(), -3000);
+        assert_eq!(clock_sample.round_trip(), 20);
+    }
+
+    #[test]
+    fn asymmetric_delay_skews_offset_by_half_the_difference() {
+        //vantage clock 1000ms behind, 10ms to the bridge and 90ms back
+        let clock_sample = ClockSample { originate: 0, receive: 1010, transmit: 1030, destination: 120 };
+        assert_eq!(clock_sample.offset(), 960);
+        assert_eq!(clock_sample.round_trip(), 100);
+    }
+}
@@ -13,6 +13,7 @@ pub enum ProddleError {
     Bincode(Box<bincode::ErrorKind>),
     Clap(clap::Error),
     Curl(curl::Error),
+    CurlMulti(curl::MultiError),
     DecoderError(bson::DecoderError),
     EncoderError(bson::EncoderError),
     Io(std::io::Error),
@@ -28,6 +29,7 @@ impl Display for ProddleError {
             ProddleError::Bincode(ref err) => write!(f, "Bincode: {}", err),
             ProddleError::Clap(ref err) => write!(f, "ClapError: {}", err),
             ProddleError::Curl(ref err) => write!(f, "CurlError: {}", err),
+            ProddleError::CurlMulti(ref err) => write!(f, "CurlMultiError: {}", err),
             ProddleError::DecoderError(ref err) => write!(f, "DecoderError: {}", err),
             ProddleError::EncoderError(ref err) => write!(f, "EncoderError: {}", err),
             ProddleError::Io(ref err) => write!(f, "IoError: {}", err),
@@ -62,6 +64,12 @@ impl From<curl::Error> for ProddleError {
     }
 }
 
+impl From<curl::MultiError> for ProddleError {
+    fn from(err: curl::MultiError) -> ProddleError {
+        ProddleError::CurlMulti(err)
+    }
+}
+
 impl From<bson::DecoderError> for ProddleError {
     fn from(err: bson::DecoderError) -> ProddleError {
         ProddleError::DecoderError(err)
@@ -0,0 +1,271 @@
+use Operation;
+
+/// Version of the hash functions below. Bridges and vantages only compare hashes computed with
+/// the same version, it changes whenever the algorithm, keys or value encodings change. Messages
+/// are bincode encoded without field names, so the version is only checked for vantages whose
+/// update requests already carry it and decode on the bridge.
+pub const HASH_VERSION: u32 = 1;
+
+//fixed siphash keys, "proddle-" and "buckets1" read as little endian integers
+static KEY_0: u64 = 0x2d656c64646f7270;
+static KEY_1: u64 = 0x317374656b637562;
+
+/// SipHash-2-4 with fixed keys. Values are written with explicit little endian, length
+/// prefixed encodings so hashes do not depend on the platform or the Rust release.
+#[derive(Clone)]
+pub struct StableHasher {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    tail: u64,
+    tail_length: usize,
+    length: usize,
+}
+
+impl StableHasher {
+    pub fn new() -> StableHasher {
+        StableHasher::with_keys(KEY_0, KEY_1)
+    }
+
+    fn with_keys(key_0: u64, key_1: u64) -> StableHasher {
+        StableHasher {
+            v0: key_0 ^ 0x736f6d6570736575,
+            v1: key_1 ^ 0x646f72616e646f6d,
+            v2: key_0 ^ 0x6c7967656e657261,
+            v3: key_1 ^ 0x7465646279746573,
+            tail: 0,
+            tail_length: 0,
+            length: 0,
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.tail |= (*byte as u64) << (8 * self.tail_length);
+            self.tail_length += 1;
+            self.length += 1;
+            if self.tail_length == 8 {
+                let message = self.tail;
+                self.compress(message);
+                self.tail = 0;
+                self.tail_length = 0;
+            }
+        }
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        let mut bytes = [0; 8];
+        for i in 0..8 {
+            bytes[i] = (value >> (8 * i)) as u8;
+        }
+
+        self.write(&bytes);
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.write_u64(value as u64);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write(&[value as u8]);
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.write(value.as_bytes());
+    }
+
+    pub fn write_option_str(&mut self, value: &Option<String>) {
+        match *value {
+            Some(ref value) => {
+                self.write_bool(true);
+                self.write_str(value);
+            },
+            None => self.write_bool(false),
+        }
+    }
+
+    pub fn write_option_i64(&mut self, value: Option<i64>) {
+        match value {
+            Some(value) => {
+                self.write_bool(true);
+                self.write_i64(value);
+            },
+            None => self.write_bool(false),
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        let mut hasher = self.clone();
+        let message = ((self.length as u64 & 0xff) << 56) | self.tail;
+        hasher.compress(message);
+        hasher.v2 ^= 0xff;
+        for _ in 0..4 {
+            hasher.round();
+        }
+
+        hasher.v0 ^ hasher.v1 ^ hasher.v2 ^ hasher.v3
+    }
+
+    fn compress(&mut self, message: u64) {
+        self.v3 ^= message;
+        self.round();
+        self.round();
+        self.v0 ^= message;
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13);
+        self.v1 ^= self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16);
+        self.v3 ^= self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21);
+        self.v3 ^= self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17);
+        self.v1 ^= self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+}
+
+/// Hash of a string, used to assign operation domains to buckets.
+pub fn hash_string(value: &str) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write_str(value);
+    hasher.finish()
+}
+
+/// Hash of every field of an operation.
+pub fn hash_operation(operation: &Operation) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write_i64(operation.version);
+    hasher.write_bool(operation.deleted);
+    hasher.write_i64(operation.timestamp);
+    hasher.write_str(&operation.measurement_class);
+    hasher.write_str(&operation.domain);
+
+    hasher.write_u64(operation.parameters.len() as u64);
+    for parameter in operation.parameters.iter() {
+        hasher.write_str(&parameter.name);
+        hasher.write_str(&parameter.value);
+    }
+
+    hasher.write_u64(operation.tags.len() as u64);
+    for tag in operation.tags.iter() {
+        hasher.write_str(tag);
+    }
+
+    hasher.write_option_str(&operation.schedule);
+    hasher.write_option_i64(operation.start_timestamp);
+    hasher.write_option_i64(operation.end_timestamp);
+    hasher.finish()
+}
+
+/// Digest of a bucket of operations which does not depend on their order, so a bridge and
+/// vantage holding the same operations agree however each arrived at them.
+pub fn bucket_digest<'a, I: IntoIterator<Item=&'a Operation>>(operations: I) -> u64 {
+    let mut count = 0u64;
+    let mut sum = 0u64;
+    for operation in operations {
+        count += 1;
+        sum = sum.wrapping_add(hash_operation(operation));
+    }
+
+    let mut hasher = StableHasher::new();
+    hasher.write_u64(count);
+    hasher.write_u64(sum);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Parameter;
+
+    //siphash-2-4 reference outputs for key 00..0f and inputs 00..(n-1) of each length n
+    static SIPHASH_VECTORS: [u64; 64] = [
+        0x726fdb47dd0e0e31, 0x74f839c593dc67fd, 0x0d6c8009d9a94f5a, 0x85676696d7fb7e2d,
+        0xcf2794e0277187b7, 0x18765564cd99a68d, 0xcbc9466e58fee3ce, 0xab0200f58b01d137,
+        0x93f5f5799a932462, 0x9e0082df0ba9e4b0, 0x7a5dbbc594ddb9f3, 0xf4b32f46226bada7,
+        0x751e8fbc860ee5fb, 0x14ea5627c0843d90, 0xf723ca908e7af2ee, 0xa129ca6149be45e5,
+        0x3f2acc7f57c29bdb, 0x699ae9f52cbe4794, 0x4bc1b3f0968dd39c, 0xbb6dc91da77961bd,
+        0xbed65cf21aa2ee98, 0xd0f2cbb02e3b67c7, 0x93536795e3a33e88, 0xa80c038ccd5ccec8,
+        0xb8ad50c6f649af94, 0xbce192de8a85b8ea, 0x17d835b85bbb15f3, 0x2f2e6163076bcfad,
+        0xde4daaaca71dc9a5, 0xa6a2506687956571, 0xad87a3535c49ef28, 0x32d892fad841c342,
+        0x7127512f72f27cce, 0xa7f32346f95978e3, 0x12e0b01abb051238, 0x15e034d40fa197ae,
+        0x314dffbe0815a3b4, 0x027990f029623981, 0xcadcd4e59ef40c4d, 0x9abfd8766a33735c,
+        0x0e3ea96b5304a7d0, 0xad0c42d6fc585992, 0x187306c89bc215a9, 0xd4a60abcf3792b95,
+        0xf935451de4f21df2, 0xa9538f0419755787, 0xdb9acddff56ca510, 0xd06c98cd5c0975eb,
+        0xe612a3cb9ecba951, 0xc766e62cfcadaf96, 0xee64435a9752fe72, 0xa192d576b245165a,
+        0x0a8787bf8ecb74b2, 0x81b3e73d20b49b6f, 0x7fa8220ba3b2ecea, 0x245731c13ca42499,
+        0xb78dbfaf3a8d83bd, 0xea1ad565322a1a0b, 0x60e61c23a3795013, 0x6606d7e446282b93,
+        0x6ca4ecb15c5f91e1, 0x9f626da15c9625f3, 0xe51b38608ef25f57, 0x958a324ceb064572,
+    ];
+
+    //fixed values, a change to any of them requires a new HASH_VERSION
+    static HASH_STRING_EMPTY: u64 = 0x2e7ca6cac874127c;
+    static HASH_STRING_GOOGLE: u64 = 0xe79738a8cf6c152b;
+    static BUCKET_DIGEST: u64 = 0x05357665536ee0ed;
+    static BUCKET_DIGEST_EMPTY: u64 = 0x3176ecb051dfa1a2;
+
+    fn operation(domain: &str, version: i64) -> Operation {
+        Operation {
+            version: version,
+            deleted: false,
+            timestamp: 1500000000,
+            measurement_class: String::from("HttpGet"),
+            domain: domain.to_owned(),
+            parameters: vec![Parameter { name: String::from("timeout"), value: String::from("30") }],
+            tags: vec![String::from("top-sites")],
+            schedule: None,
+            start_timestamp: None,
+            end_timestamp: None,
+        }
+    }
+
+    #[test]
+    fn matches_siphash_reference_vectors() {
+        let input: Vec<u8> = (0..64).collect();
+        for (length, expected) in SIPHASH_VECTORS.iter().enumerate() {
+            let mut hasher = StableHasher::with_keys(0x0706050403020100, 0x0f0e0d0c0b0a0908);
+            hasher.write(&input[..length]);
+            assert_eq!(hasher.finish(), *expected, "input length {}", length);
+        }
+    }
+
+    #[test]
+    fn split_writes_match_a_single_write() {
+        let input: Vec<u8> = (0..64).collect();
+        let mut hasher = StableHasher::with_keys(0x0706050403020100, 0x0f0e0d0c0b0a0908);
+        for chunk in input[..63].chunks(5) {
+            hasher.write(chunk);
+        }
+
+        assert_eq!(hasher.finish(), SIPHASH_VECTORS[63]);
+    }
+
+    #[test]
+    fn hash_string_is_stable() {
+        assert_eq!(hash_string(""), HASH_STRING_EMPTY);
+        assert_eq!(hash_string("google.com"), HASH_STRING_GOOGLE);
+    }
+
+    #[test]
+    fn bucket_digest_is_stable_and_order_independent() {
+        let operations = vec![operation("google.com", 1), operation("example.com", 2)];
+        let reversed: Vec<Operation> = operations.iter().rev().cloned().collect();
+        assert_eq!(bucket_digest(&operations), BUCKET_DIGEST);
+        assert_eq!(bucket_digest(&reversed), BUCKET_DIGEST);
+        assert_eq!(bucket_digest(&Vec::new()), BUCKET_DIGEST_EMPTY);
+
+        //any changed field changes the digest
+        let mut changed = operations.clone();
+        changed[1].version = 3;
+        assert!(bucket_digest(&changed) != BUCKET_DIGEST);
+    }
+}
@@ -6,15 +6,28 @@ extern crate mongodb;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate slog;
+#[macro_use]
+extern crate slog_scope;
 
 use bincode::Infinite;
 
+mod bucket_ring;
+mod clock;
 mod error;
+mod hash;
+mod metrics;
+mod schedule;
 
+pub use self::bucket_ring::BucketRing;
+pub use self::clock::{ClockSample, timestamp_milliseconds};
 pub use self::error::ProddleError;
+pub use self::hash::{HASH_VERSION, StableHasher, bucket_digest, hash_operation, hash_string};
+pub use self::metrics::{MetricsSource, serve_metrics, write_metric};
+pub use self::schedule::Schedule;
 
 use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
 use std::io::{Read, Write};
 use std::net::TcpStream;
 
@@ -26,119 +39,434 @@ pub enum MessageType {
     UpdateOperationsResponse,
     SendMeasurementsRequest,
     SendMeasurementsResponse,
+    SendStatisticsRequest,
+    SendStatisticsResponse,
+    HeartbeatRequest,
+    HeartbeatResponse,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Message {
     pub message_type: MessageType,
+    pub vantage_id: Option<String>,
     pub error: Option<String>,
-    pub update_operations_request: Option<HashMap<u64, u64>>,
+    pub update_operations_request: Option<UpdateOperationsRequest>,
     pub update_operations_response: Option<HashMap<u64, Vec<Operation>>>,
+    pub operation_delta: Option<OperationDelta>,
+    pub operations_version: Option<i64>,
+    pub vantage_config: Option<VantageConfig>,
+    pub peer_address: Option<String>,
     pub send_measurements_request: Option<Vec<Vec<u8>>>,
     pub send_measurements_response: Option<Vec<usize>>,
+    pub send_statistics_request: Option<Vec<u8>>,
+    pub heartbeat_request: Option<Heartbeat>,
+    pub transmit_timestamp: Option<i64>,
+    pub receive_timestamp: Option<i64>,
+    pub clock_sample: Option<ClockSample>,
 }
 
 impl Message {
+    /// Identifies the vantage sending a request.
+    pub fn with_vantage_id(mut self, vantage_id: &str) -> Message {
+        self.vantage_id = Some(vantage_id.to_owned());
+        self
+    }
+
+    /// Sets the sender clock timestamps in milliseconds, a response also carries the time its
+    /// request was received so the vantage can sample the clock offset of each exchange.
+    pub fn with_timestamps(mut self, receive_timestamp: Option<i64>, transmit_timestamp: i64) -> Message {
+        self.receive_timestamp = receive_timestamp;
+        self.transmit_timestamp = Some(transmit_timestamp);
+        self
+    }
+
+    /// Reports the timestamps of the previous exchange so the bridge can estimate the vantage
+    /// clock offset.
+    pub fn with_clock_sample(mut self, clock_sample: Option<ClockSample>) -> Message {
+        self.clock_sample = clock_sample;
+        self
+    }
+
     pub fn error(error: String) -> Message {
         Message {
             message_type: MessageType::Error,
+            vantage_id: None,
             error: Some(error),
             update_operations_request: None,
             update_operations_response: None,
+            operation_delta: None,
+            operations_version: None,
+            vantage_config: None,
+            peer_address: None,
             send_measurements_request: None,
             send_measurements_response: None,
+            send_statistics_request: None,
+            heartbeat_request: None,
+            transmit_timestamp: None,
+            receive_timestamp: None,
+            clock_sample: None,
         }
     }
 
-    pub fn update_operations_request(operation_bucket_hashes: HashMap<u64, u64>) -> Message {
+    pub fn update_operations_request(vantage_hostname: String, vantage_groups: Vec<String>, config_version: i64,
+                                     operation_bucket_hashes: HashMap<u64, u64>, operations_version: Option<i64>,
+                                     tag_filter: TagFilter) -> Message {
         Message {
             message_type: MessageType::UpdateOperationsRequest,
+            vantage_id: None,
             error: None,
-            update_operations_request: Some(operation_bucket_hashes),
+            update_operations_request: Some(
+                UpdateOperationsRequest {
+                    vantage_hostname: vantage_hostname,
+                    vantage_groups: vantage_groups,
+                    config_version: config_version,
+                    operation_bucket_hashes: operation_bucket_hashes,
+                    operations_version: operations_version,
+                    hash_version: HASH_VERSION,
+                    tag_filter: tag_filter,
+                }
+            ),
             update_operations_response: None,
+            operation_delta: None,
+            operations_version: None,
+            vantage_config: None,
+            peer_address: None,
             send_measurements_request: None,
             send_measurements_response: None,
+            send_statistics_request: None,
+            heartbeat_request: None,
+            transmit_timestamp: None,
+            receive_timestamp: None,
+            clock_sample: None,
         }
     }
 
-    pub fn update_operations_response(operation_buckets: HashMap<u64, Vec<Operation>>) -> Message {
+    /// Full operation buckets replace the vantage's buckets while a delta is applied to them,
+    /// either way the vantage is then synchronized to the operations version.
+    pub fn update_operations_response(operation_buckets: HashMap<u64, Vec<Operation>>, operation_delta: Option<OperationDelta>,
+                                      operations_version: i64, vantage_config: Option<VantageConfig>,
+                                      peer_address: Option<String>) -> Message {
         Message {
             message_type: MessageType::UpdateOperationsResponse,
+            vantage_id: None,
             error: None,
             update_operations_request: None,
             update_operations_response: Some(operation_buckets),
+            operation_delta: operation_delta,
+            operations_version: Some(operations_version),
+            vantage_config: vantage_config,
+            peer_address: peer_address,
             send_measurements_request: None,
             send_measurements_response: None,
+            send_statistics_request: None,
+            heartbeat_request: None,
+            transmit_timestamp: None,
+            receive_timestamp: None,
+            clock_sample: None,
         }
     }
 
     pub fn send_measurements_request(measurements: Vec<Vec<u8>>) -> Message {
         Message {
             message_type: MessageType::SendMeasurementsRequest,
+            vantage_id: None,
             error: None,
             update_operations_request: None,
             update_operations_response: None,
+            operation_delta: None,
+            operations_version: None,
+            vantage_config: None,
+            peer_address: None,
             send_measurements_request: Some(measurements),
             send_measurements_response: None,
+            send_statistics_request: None,
+            heartbeat_request: None,
+            transmit_timestamp: None,
+            receive_timestamp: None,
+            clock_sample: None,
         }
     }
 
     pub fn send_measurements_response(measurement_failures: Vec<usize>) -> Message {
         Message {
             message_type: MessageType::SendMeasurementsResponse,
+            vantage_id: None,
             error: None,
             update_operations_request: None,
             update_operations_response: None,
+            operation_delta: None,
+            operations_version: None,
+            vantage_config: None,
+            peer_address: None,
             send_measurements_request: None,
             send_measurements_response: Some(measurement_failures),
+            send_statistics_request: None,
+            heartbeat_request: None,
+            transmit_timestamp: None,
+            receive_timestamp: None,
+            clock_sample: None,
+        }
+    }
+
+    pub fn send_statistics_request(statistics: Vec<u8>) -> Message {
+        Message {
+            message_type: MessageType::SendStatisticsRequest,
+            vantage_id: None,
+            error: None,
+            update_operations_request: None,
+            update_operations_response: None,
+            operation_delta: None,
+            operations_version: None,
+            vantage_config: None,
+            peer_address: None,
+            send_measurements_request: None,
+            send_measurements_response: None,
+            send_statistics_request: Some(statistics),
+            heartbeat_request: None,
+            transmit_timestamp: None,
+            receive_timestamp: None,
+            clock_sample: None,
+        }
+    }
+
+    pub fn send_statistics_response() -> Message {
+        Message {
+            message_type: MessageType::SendStatisticsResponse,
+            vantage_id: None,
+            error: None,
+            update_operations_request: None,
+            update_operations_response: None,
+            operation_delta: None,
+            operations_version: None,
+            vantage_config: None,
+            peer_address: None,
+            send_measurements_request: None,
+            send_measurements_response: None,
+            send_statistics_request: None,
+            heartbeat_request: None,
+            transmit_timestamp: None,
+            receive_timestamp: None,
+            clock_sample: None,
+        }
+    }
+
+    pub fn heartbeat_request(heartbeat: Heartbeat) -> Message {
+        Message {
+            message_type: MessageType::HeartbeatRequest,
+            vantage_id: None,
+            error: None,
+            update_operations_request: None,
+            update_operations_response: None,
+            operation_delta: None,
+            operations_version: None,
+            vantage_config: None,
+            peer_address: None,
+            send_measurements_request: None,
+            send_measurements_response: None,
+            send_statistics_request: None,
+            heartbeat_request: Some(heartbeat),
+            transmit_timestamp: None,
+            receive_timestamp: None,
+            clock_sample: None,
+        }
+    }
+
+    pub fn heartbeat_response() -> Message {
+        Message {
+            message_type: MessageType::HeartbeatResponse,
+            vantage_id: None,
+            error: None,
+            update_operations_request: None,
+            update_operations_response: None,
+            operation_delta: None,
+            operations_version: None,
+            vantage_config: None,
+            peer_address: None,
+            send_measurements_request: None,
+            send_measurements_response: None,
+            send_statistics_request: None,
+            heartbeat_request: None,
+            transmit_timestamp: None,
+            receive_timestamp: None,
+            clock_sample: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdateOperationsRequest {
+    pub vantage_hostname: String,
+    pub vantage_groups: Vec<String>,
+    pub config_version: i64,
+    pub operation_bucket_hashes: HashMap<u64, u64>,
+    pub operations_version: Option<i64>,
+    pub hash_version: u32,
+    pub tag_filter: TagFilter,
+}
+
+/// Tags selecting the operations a vantage performs, an operation is selected if it carries
+/// an include tag and no exclude tag. The bridge only sends and hashes selected operations.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct TagFilter {
+    pub include_tags: Vec<String>,
+    pub exclude_tags: Vec<String>,
+}
+
+impl TagFilter {
+    /// Sorts and deduplicates the tags so equal filters compare equal.
+    pub fn new(mut include_tags: Vec<String>, mut exclude_tags: Vec<String>) -> TagFilter {
+        include_tags.sort();
+        include_tags.dedup();
+        exclude_tags.sort();
+        exclude_tags.dedup();
+
+        TagFilter {
+            include_tags: include_tags,
+            exclude_tags: exclude_tags,
         }
     }
+
+    pub fn matches(&self, operation: &Operation) -> bool {
+        operation.tags.iter().any(|x| self.include_tags.contains(x)) && !operation.tags.iter().any(|x| self.exclude_tags.contains(x))
+    }
 }
 
+/// Operations changed since the operations version a vantage last synchronized to, including
+/// tombstones of deleted operations, with the bridge hashes of the buckets they belong to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OperationDelta {
+    pub operations: HashMap<u64, Vec<Operation>>,
+    pub operation_bucket_hashes: HashMap<u64, u64>,
+}
+
+/// Liveness report sent periodically by each vantage.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Heartbeat {
+    pub vantage_hostname: String,
+    pub version: String,
+    pub uptime_seconds: i64,
+    pub scheduled_operations: u64,
+    pub queue_depth: u64,
+    pub in_flight: u64,
+    pub measurement_buffer_depth: u64,
+}
+
+/// Measurement to perform on a domain. The version is assigned by the store and increases with
+/// every change, a deleted operation is kept as a tombstone so the deletion is synchronized.
+/// Operations are identified by measurement class and domain.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Operation {
+    #[serde(default)]
+    pub version: i64,
+    #[serde(default)]
+    pub deleted: bool,
     pub timestamp: i64,
     pub measurement_class: String,
     pub domain: String,
     pub parameters: Vec<Parameter>,
     pub tags: Vec<String>,
+    pub schedule: Option<String>,
+    pub start_timestamp: Option<i64>,
+    pub end_timestamp: Option<i64>,
 }
 
-impl Hash for Operation {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.timestamp.hash(state);
-        self.measurement_class.hash(state);
-        self.domain.hash(state);
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Parameter {
+    pub name: String,
+    pub value: String,
+}
 
-        for parameter in self.parameters.iter() {
-            parameter.name.hash(state);
-            parameter.value.hash(state);
+/// Vantage settings managed on the bridge, stored for either a single vantage hostname or a
+/// group of vantages. Unset fields leave the vantage's local setting in place.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct VantageConfig {
+    pub hostname: Option<String>,
+    pub group: Option<String>,
+    pub timestamp: i64,
+    pub include_tags: Option<HashMap<String, i64>>,
+    pub exclude_tags: Option<Vec<String>>,
+    pub max_jitter_seconds: Option<i64>,
+}
+
+impl VantageConfig {
+    /// Overrides fields with those set in 'vantage_config'.
+    pub fn merge(&mut self, vantage_config: &VantageConfig) {
+        if vantage_config.include_tags.is_some() {
+            self.include_tags = vantage_config.include_tags.clone();
+        }
+
+        if vantage_config.exclude_tags.is_some() {
+            self.exclude_tags = vantage_config.exclude_tags.clone();
         }
 
-        for tag in self.tags.iter() {
-            tag.hash(state);
+        if vantage_config.max_jitter_seconds.is_some() {
+            self.max_jitter_seconds = vantage_config.max_jitter_seconds;
         }
     }
-}
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct Parameter {
-    pub name: String,
-    pub value: String,
+    /// Version identifying the settings of a merged configuration, 0 when none are set. It is
+    /// derived from the settings rather than the timestamps of the merged configurations so
+    /// deleting or replacing any of them changes the version whenever the settings change.
+    pub fn content_version(&self) -> i64 {
+        if self.include_tags.is_none() && self.exclude_tags.is_none() && self.max_jitter_seconds.is_none() {
+            return 0;
+        }
+
+        let mut hasher = StableHasher::new();
+        match self.include_tags {
+            Some(ref include_tags) => {
+                let mut include_tags: Vec<(&String, &i64)> = include_tags.iter().collect();
+                include_tags.sort();
+                hasher.write_bool(true);
+                hasher.write_u64(include_tags.len() as u64);
+                for (tag, interval) in include_tags {
+                    hasher.write_str(tag);
+                    hasher.write_i64(*interval);
+                }
+            },
+            None => hasher.write_bool(false),
+        }
+
+        match self.exclude_tags {
+            Some(ref exclude_tags) => {
+                hasher.write_bool(true);
+                hasher.write_u64(exclude_tags.len() as u64);
+                for tag in exclude_tags.iter() {
+                    hasher.write_str(tag);
+                }
+            },
+            None => hasher.write_bool(false),
+        }
+
+        hasher.write_option_i64(self.max_jitter_seconds);
+
+        //keep versions positive and reserve 0 for no configuration
+        match (hasher.finish() & (i64::max_value() as u64)) as i64 {
+            0 => 1,
+            version => version,
+        }
+    }
 }
 
-pub fn message_to_stream(message: &Message, stream: &mut TcpStream) -> Result<(), ProddleError> {
+/// Writes the length prefixed message, returning the number of bytes written.
+pub fn message_to_stream(message: &Message, stream: &mut TcpStream) -> Result<usize, ProddleError> {
     let encoded: Vec<u8> = bincode::serialize(message, Infinite).unwrap();
     let length = encoded.len() as u32;
 
-    try!(stream.write(&[(length as u8), ((length >> 8) as u8), ((length >> 16) as u8), ((length >> 24) as u8)]));
+    try!(stream.write_all(&[(length as u8), ((length >> 8) as u8), ((length >> 16) as u8), ((length >> 24) as u8)]));
     try!(stream.write_all(&encoded));
     try!(stream.flush());
 
-    Ok(())
+    Ok(encoded.len() + 4)
 }
 
 pub fn message_from_stream(stream: &mut TcpStream) -> Result<Message, ProddleError> {
+    let (message, _) = try!(message_from_stream_with_length(stream));
+    Ok(message)
+}
+
+/// Reads a length prefixed message, also returning the number of bytes read.
+pub fn message_from_stream_with_length(stream: &mut TcpStream) -> Result<(Message, usize), ProddleError> {
     let mut length_buffer = vec![0u8; 4];
     try!(stream.read_exact(&mut length_buffer));
     let length = ((length_buffer[0] as u32) | ((length_buffer[1] as u32) << 8) | ((length_buffer[2] as u32) << 16) | ((length_buffer[3] as u32) << 24)) as usize;
@@ -146,5 +474,5 @@ pub fn message_from_stream(stream: &mut TcpStream) -> Result<Message, ProddleErr
     let mut byte_buffer = vec![0u8; length];
     try!(stream.read_exact(&mut byte_buffer));
     let message = try!(bincode::deserialize(&byte_buffer));
-    Ok(message)
+    Ok((message, length + 4))
 }
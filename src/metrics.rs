@@ -0,0 +1,71 @@
+use ProddleError;
+
+use std;
+use std::fmt::Write as FmtWrite;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// State exposed by a metrics endpoint in the prometheus text format and as a json status page.
+pub trait MetricsSource {
+    fn to_prometheus(&self) -> String;
+    fn to_json(&self) -> String;
+}
+
+/// Appends a metric in the prometheus text format, each sample is a label set and value.
+pub fn write_metric(output: &mut String, name: &str, help: &str, metric_type: &str, samples: &[(String, f64)]) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+    for &(ref labels, value) in samples.iter() {
+        let _ = writeln!(output, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+/// Serves prometheus metrics at '/metrics' and the json status page at '/status'.
+pub fn serve_metrics<T: MetricsSource + Send + 'static>(address: &str, metrics: Arc<Mutex<T>>) -> Result<(), ProddleError> {
+    let listener = try!(TcpListener::bind(try!(SocketAddr::from_str(address))));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    if let Err(e) = handle_stream(&mut stream, &metrics) {
+                        warn!("failed to serve metrics request: {}", e);
+                    }
+                },
+                Err(e) => error!("recv metrics connection failed: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_stream<T: MetricsSource>(stream: &mut TcpStream, metrics: &Mutex<T>) -> Result<(), ProddleError> {
+    try!(stream.set_read_timeout(Some(Duration::new(5, 0))));
+    try!(stream.set_write_timeout(Some(Duration::new(5, 0))));
+
+    //read request line and discard headers
+    let mut reader = BufReader::new(try!(stream.try_clone()));
+    let mut request_line = String::new();
+    try!(reader.read_line(&mut request_line));
+    loop {
+        let mut line = String::new();
+        if try!(reader.read_line(&mut line)) == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, content_type, body) = match path {
+        "/metrics" => ("200 OK", "text/plain; version=0.0.4", metrics.lock().unwrap().to_prometheus()),
+        "/status" => ("200 OK", "application/json", metrics.lock().unwrap().to_json()),
+        _ => ("404 Not Found", "text/plain", String::from("not found\n")),
+    };
+
+    try!(write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body));
+    try!(stream.flush());
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use error::ProddleError;
+
+//maximum number of field mismatches evaluated before a schedule is considered unsatisfiable
+static MAX_SEARCH_ITERATIONS: usize = 100000;
+
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    Once,
+    Cron(CronSchedule),
+}
+
+#[derive(Clone, Debug)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+impl Schedule {
+    /// Parses a five field cron expression (minute hour day-of-month month day-of-week), one of the
+    /// '@yearly', '@monthly', '@weekly', '@daily' and '@hourly' shorthands, or '@once'.
+    pub fn parse(expression: &str) -> Result<Schedule, ProddleError> {
+        let expression = match expression.trim() {
+            "@once" => return Ok(Schedule::Once),
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expression => expression,
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ProddleError::from(format!("cron expression '{}' must contain 5 fields", expression)));
+        }
+
+        let mut days_of_week = try!(parse_field(fields[4], 0, 7));
+        if days_of_week & (1 << 7) != 0 {
+            //both 0 and 7 represent sunday
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(Schedule::Cron(
+            CronSchedule {
+                minutes: try!(parse_field(fields[0], 0, 59)),
+                hours: try!(parse_field(fields[1], 0, 23)),
+                days_of_month: try!(parse_field(fields[2], 1, 31)),
+                months: try!(parse_field(fields[3], 1, 12)),
+                days_of_week: days_of_week,
+                //fields starting with '*', including steps such as '*/2', do not restrict the day
+                day_of_month_restricted: !fields[2].starts_with("*"),
+                day_of_week_restricted: !fields[4].starts_with("*"),
+            }
+        ))
+    }
+
+    /// Returns the first timestamp strictly after 'timestamp' matching the schedule. One-shot
+    /// schedules never have a next execution.
+    pub fn next_after(&self, timestamp: i64) -> Option<i64> {
+        match *self {
+            Schedule::Once => None,
+            Schedule::Cron(ref cron_schedule) => cron_schedule.next_after(timestamp),
+        }
+    }
+}
+
+impl CronSchedule {
+    fn next_after(&self, timestamp: i64) -> Option<i64> {
+        //start at the next whole minute
+        let mut t = (timestamp.div_euclid(60) + 1) * 60;
+
+        for _ in 0..MAX_SEARCH_ITERATIONS {
+            let days = t.div_euclid(86400);
+            let seconds = t.rem_euclid(86400);
+            let (year, month, day) = civil_from_days(days);
+
+            //check month
+            if self.months & (1 << month) == 0 {
+                let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+                t = days_from_civil(next_year, next_month, 1) * 86400;
+                continue;
+            }
+
+            //check day, a day matches either restricted field as in traditional cron
+            let day_of_week = (days + 4).rem_euclid(7) as u32;
+            let day_of_month_match = self.days_of_month & (1 << day) != 0;
+            let day_of_week_match = self.days_of_week & (1 << day_of_week) != 0;
+            let day_match = match (self.day_of_month_restricted, self.day_of_week_restricted) {
+                (true, true) => day_of_month_match || day_of_week_match,
+                _ => day_of_month_match && day_of_week_match,
+            };
+
+            if !day_match {
+                t = (days + 1) * 86400;
+                continue;
+            }
+
+            //check hour
+            let hour = seconds / 3600;
+            if self.hours & (1 << hour) == 0 {
+                t = days * 86400 + (hour + 1) * 3600;
+                continue;
+            }
+
+            //check minute
+            let minute = (seconds % 3600) / 60;
+            if self.minutes & (1 << minute) == 0 {
+                t = days * 86400 + hour * 3600 + (minute + 1) * 60;
+                continue;
+            }
+
+            return Some(t);
+        }
+
+        None
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, ProddleError> {
+    let mut bits = 0u64;
+    for part in field.split(",") {
+        let mut split_values = part.split("/");
+        let range = try!(split_values.nth(0).ok_or("failed to parse cron field range"));
+        let step = match split_values.nth(0) {
+            Some(step) => try!(step.parse::<u32>()),
+            None => 1,
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some(index) = range.find("-") {
+            (try!(range[..index].parse::<u32>()), try!(range[index+1..].parse::<u32>()))
+        } else {
+            let value = try!(range.parse::<u32>());
+            if part.contains("/") { (value, max) } else { (value, value) }
+        };
+
+        if step == 0 || start < min || end > max || start > end {
+            return Err(ProddleError::from(format!("invalid cron field '{}', values must be within {}-{}", field, min, max)));
+        }
+
+        let mut value = start;
+        while value <= end {
+            bits |= 1 << value;
+            value += step;
+        }
+    }
+
+    Ok(bits)
+}
+
+//convert days since the unix epoch into a (year, month, day) tuple
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = year_of_era + era * 400;
+
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+//convert a (year, month, day) tuple into days since the unix epoch
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let mp = (if month > 2 { month - 3 } else { month + 9 }) as i64;
+    let day_of_year = (153 * mp + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //successive execution times strictly after 'timestamp'
+    fn executions(expression: &str, timestamp: i64, count: usize) -> Vec<i64> {
+        let schedule = Schedule::parse(expression).unwrap();
+        let mut executions = Vec::new();
+        let mut timestamp = timestamp;
+        for _ in 0..count {
+            timestamp = schedule.next_after(timestamp).unwrap();
+            executions.push(timestamp);
+        }
+
+        executions
+    }
+
+    #[test]
+    fn field_values_outside_their_range_are_rejected() {
+        for expression in ["60 * * * *", "* 24 * * *", "* * 0 * *", "* * 32 * *", "* * * 0 *", "* * * 13 *", "* * * * 8",
+                "5-3 * * * *", "*/0 * * * *", "a * * * *", "* * * *", "* * * * * *"].iter() {
+            assert!(Schedule::parse(expression).is_err(), "accepted '{}'", expression);
+        }
+
+        for expression in ["0-59 0-23 1-31 1-12 0-7", "59 23 31 12 7", "@once", "@yearly", "@hourly"].iter() {
+            assert!(Schedule::parse(expression).is_ok(), "rejected '{}'", expression);
+        }
+    }
+
+    #[test]
+    fn lists_ranges_and_steps_select_minutes() {
+        assert_eq!(executions("0,15,30-31 * * * *", 0, 5), vec![900, 1800, 1860, 3600, 4500]);
+        assert_eq!(executions("*/20 * * * *", 0, 3), vec![1200, 2400, 3600]);
+        assert_eq!(executions("10/20 * * * *", 0, 4), vec![600, 1800, 3000, 4200]);
+        assert_eq!(executions("10-40/15 * * * *", 0, 4), vec![600, 1500, 2400, 4200]);
+    }
+
+    #[test]
+    fn sunday_is_both_zero_and_seven() {
+        //1970-01-04 was the first sunday after the epoch
+        assert_eq!(executions("0 0 * * 7", 0, 2), vec![259200, 864000]);
+        assert_eq!(executions("0 0 * * 0", 0, 2), vec![259200, 864000]);
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either_day() {
+        //the 13th of each month or any friday, the epoch was a thursday
+        assert_eq!(executions("0 0 13 * 5", 0, 4), vec![86400, 691200, 1036800, 1296000]);
+    }
+
+    #[test]
+    fn stepped_wildcard_day_fields_are_not_restricted() {
+        //odd days which are also fridays, 1970-01-09 is the first
+        assert_eq!(executions("0 0 */2 * 5", 0, 2), vec![691200, 1900800]);
+
+        //first days of a month falling on a sunday, tuesday, thursday or saturday
+        assert_eq!(executions("0 0 1 * */2", 0, 1), vec![2678400]);
+    }
+
+    #[test]
+    fn days_missing_from_a_month_roll_over_to_the_next_match() {
+        //1970-01-31 is followed by 1970-03-31
+        assert_eq!(executions("0 0 31 * *", 2592000 - 1, 2), vec![2592000, 7689600]);
+
+        //the 29th of february only exists in leap years
+        assert_eq!(executions("0 0 29 2 *", 0, 1), vec![68169600]);
+
+        //the last minute of the year rolls over into the next year
+        assert_eq!(executions("59 23 31 12 *", 0, 2), vec![31535940, 63071940]);
+    }
+
+    #[test]
+    fn once_has_no_next_execution() {
+        assert!(Schedule::parse("@once").unwrap().next_after(0).is_none());
+    }
+}
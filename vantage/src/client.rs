@@ -1,26 +1,50 @@
 use bson::{self, Document};
-use proddle::{self, Message, MessageType, ProddleError};
+use proddle::{self, ClockSample, Heartbeat, Message, MessageType, Operation, ProddleError, TagFilter, VantageConfig};
+use time;
 
+use config::Config;
 use operation_job::OperationJob;
 
 use std::collections::{BinaryHeap, HashMap};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use std::net::{SocketAddr, TcpStream};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::str::FromStr;
 use std::time::Duration;
 
 pub struct Client {
     socket_addr: SocketAddr,
+    vantage_id: String,
+    observed_ip_address: Option<IpAddr>,
+    clock_sample: Option<ClockSample>,
 }
 
 impl Client {
-    pub fn new(socket_addr: SocketAddr) -> Client {
+    pub fn new(socket_addr: SocketAddr, vantage_id: &str) -> Client {
         Client {
             socket_addr: socket_addr,
+            vantage_id: vantage_id.to_owned(),
+            observed_ip_address: None,
+            clock_sample: None,
         }
     }
 
-    pub fn send_measurements(&mut self, measurement_buffer: &mut Vec<Document>) -> Result<(), ProddleError> {
+    /// Returns the vantage address as observed by the bridge during the latest update.
+    pub fn observed_ip_address(&self) -> Option<IpAddr> {
+        self.observed_ip_address
+    }
+
+    /// Returns the bridge clock minus the vantage clock in milliseconds as estimated by the
+    /// latest heartbeat or operations update.
+    pub fn clock_offset(&self) -> Option<i64> {
+        self.clock_sample.as_ref().map(|x| x.offset())
+    }
+
+    pub fn set_socket_addr(&mut self, socket_addr: SocketAddr) {
+        self.socket_addr = socket_addr;
+    }
+
+    /// Sends the buffered measurements, keeping only those the bridge reports as failed. Returns
+    /// the number of measurements kept for a later send.
+    pub fn send_measurements(&mut self, measurement_buffer: &mut Vec<Document>) -> Result<usize, ProddleError> {
         //open stream
         let mut stream = try!(TcpStream::connect(self.socket_addr));
         try!(stream.set_read_timeout(Some(Duration::new(180, 0))));
@@ -33,11 +57,10 @@ impl Client {
             try!(bson::encode_document(&mut encoded, measurement));
             measurements.push(encoded);
         }
-        let request = Message::send_measurements_request(measurements);
+        let request = Message::send_measurements_request(measurements).with_vantage_id(&self.vantage_id);
 
         //send request and recv response
-        try!(proddle::message_to_stream(&request, &mut stream));
-        let response = try!(proddle::message_from_stream(&mut stream));
+        let response = try!(self.exchange(request, &mut stream, false));
         match response.message_type {
             MessageType::Error => {
                 match response.error {
@@ -46,28 +69,111 @@ impl Client {
                 }
             },
             MessageType::SendMeasurementsResponse => {
-                //TODO handle send measurements response
-                measurement_buffer.clear();
-                Ok(())
+                let measurement_failures = response.send_measurements_response.unwrap_or(Vec::new());
+                retain_failures(measurement_buffer, &measurement_failures);
+                Ok(measurement_buffer.len())
             },
             _ => Err(ProddleError::from("failed to receive SendMeasurementsResponse."))
         }
     }
 
+    pub fn send_statistics(&mut self, statistics: &Document, hostname: &str, config_version: i64) -> Result<(), ProddleError> {
+        //open stream
+        let mut stream = try!(TcpStream::connect(self.socket_addr));
+        try!(stream.set_read_timeout(Some(Duration::new(180, 0))));
+        try!(stream.set_write_timeout(Some(Duration::new(180, 0))));
+
+        //create request
+        let mut document = statistics.clone();
+        document.insert_bson(String::from("timestamp"), bson!(time::now_utc().to_timespec().sec));
+        document.insert_bson(String::from("vantage_id"), bson!(&self.vantage_id));
+        document.insert_bson(String::from("vantage_hostname"), bson!(hostname));
+        document.insert_bson(String::from("config_version"), bson!(config_version));
+
+        let mut encoded = Vec::new();
+        try!(bson::encode_document(&mut encoded, &document));
+        let request = Message::send_statistics_request(encoded).with_vantage_id(&self.vantage_id);
+
+        //send request and recv response
+        let response = try!(self.exchange(request, &mut stream, false));
+        match response.message_type {
+            MessageType::Error => {
+                match response.error {
+                    Some(error) => Err(ProddleError::from(error)),
+                    None => Err(ProddleError::from("malformed error message in send statistics")),
+                }
+            },
+            MessageType::SendStatisticsResponse => Ok(()),
+            _ => Err(ProddleError::from("failed to receive SendStatisticsResponse.")),
+        }
+    }
+
+    pub fn send_heartbeat(&mut self, heartbeat: Heartbeat) -> Result<(), ProddleError> {
+        //open stream
+        let mut stream = try!(TcpStream::connect(self.socket_addr));
+        try!(stream.set_read_timeout(Some(Duration::new(180, 0))));
+        try!(stream.set_write_timeout(Some(Duration::new(180, 0))));
+
+        //create request
+        let request = Message::heartbeat_request(heartbeat).with_vantage_id(&self.vantage_id);
+
+        //send request and recv response
+        let response = try!(self.exchange(request, &mut stream, true));
+        match response.message_type {
+            MessageType::Error => {
+                match response.error {
+                    Some(error) => Err(ProddleError::from(error)),
+                    None => Err(ProddleError::from("malformed error message in send heartbeat")),
+                }
+            },
+            MessageType::HeartbeatResponse => Ok(()),
+            _ => Err(ProddleError::from("failed to receive HeartbeatResponse.")),
+        }
+    }
+
+    //send a request and recv its response, reporting the previous clock sample to the bridge.
+    //only small exchanges are sampled, the transfer time of a large upload is counted entirely
+    //in the request direction and skews the offset by up to half of it
+    fn exchange(&mut self, request: Message, stream: &mut TcpStream, sample_clock: bool) -> Result<Message, ProddleError> {
+        let originate = proddle::timestamp_milliseconds();
+        let request = request.with_clock_sample(self.clock_sample.clone()).with_timestamps(None, originate);
+        try!(proddle::message_to_stream(&request, stream));
+        let response = try!(proddle::message_from_stream(stream));
+        if !sample_clock {
+            return Ok(response);
+        }
+
+        if let (Some(receive), Some(transmit)) = (response.receive_timestamp, response.transmit_timestamp) {
+            self.clock_sample = Some(ClockSample {
+                originate: originate,
+                receive: receive,
+                transmit: transmit,
+                destination: proddle::timestamp_milliseconds(),
+            });
+        }
+
+        Ok(response)
+    }
+
+    /// Synchronizes operations with the bridge, sending the operations version the vantage last
+    /// synchronized to so the bridge can answer with only the operations changed since, and the
+    /// configured tags so only operations the vantage performs are sent.
     pub fn update_operations(&mut self, operations: &mut HashMap<u64, BinaryHeap<OperationJob>>, 
-                             operation_bucket_hashes: &mut HashMap<u64, u64>, include_tags: &HashMap<&str, i64>, 
-                             exclude_tags: &Vec<&str>) -> Result<i32, ProddleError> {
+                             operation_bucket_hashes: &mut HashMap<u64, u64>, operations_version: &mut Option<i64>,
+                             config: &Config, config_version: i64) -> Result<(i32, Option<VantageConfig>), ProddleError> {
         //open stream
         let mut stream = try!(TcpStream::connect(self.socket_addr));
         try!(stream.set_read_timeout(Some(Duration::new(180, 0))));
         try!(stream.set_write_timeout(Some(Duration::new(180, 0))));
 
         //create request
-        let request = Message::update_operations_request(operation_bucket_hashes.clone());
+        let tag_filter = TagFilter::new(config.include_tags.keys().cloned().collect(), config.exclude_tags.clone());
+        let request = Message::update_operations_request(config.hostname.to_owned(), config.groups.clone(), config_version,
+                                                         operation_bucket_hashes.clone(), *operations_version, tag_filter)
+            .with_vantage_id(&self.vantage_id);
 
         //send request and recv response
-        try!(proddle::message_to_stream(&request, &mut stream));
-        let response = try!(proddle::message_from_stream(&mut stream));
+        let response = try!(self.exchange(request, &mut stream, true));
         match response.message_type {
             MessageType::Error => {
                 match response.error {
@@ -76,57 +182,76 @@ impl Client {
                 }
             },
             MessageType::UpdateOperationsResponse => {
+                self.observed_ip_address = response.peer_address.as_ref().and_then(|x| IpAddr::from_str(x).ok());
                 match response.update_operations_response {
                     Some(operation_buckets) => {
                         let mut updated_operations_count = 0;
 
                         //iterate over operation buckets
                         for (bucket_key, operation_vec) in operation_buckets.iter() {
+                            //index existing jobs so unchanged operations keep their schedule
+                            let mut existing_operation_jobs: HashMap<(String, String), Vec<OperationJob>> = HashMap::new();
+                            if let Some(binary_heap) = operations.remove(bucket_key) {
+                                for operation_job in binary_heap.into_vec() {
+                                    let key = (operation_job.operation.measurement_class.to_owned(), operation_job.operation.domain.to_owned());
+                                    existing_operation_jobs.entry(key).or_insert(Vec::new()).push(operation_job);
+                                }
+                            }
+
                             let mut binary_heap = BinaryHeap::new();
-                            let mut hasher = DefaultHasher::new();
                             for operation in operation_vec {
-                                operation.hash(&mut hasher);
-
-                                //validate tags
-                                let mut operation_interval = i64::max_value();
-                                //check if tag is in exclude tags
-                                let mut found = false;
-                                for operation_tag in operation.tags.iter() {
-                                    for exclude_tag in exclude_tags {
-                                        if operation_tag.eq(*exclude_tag) {
-                                            found = true;
-                                        }
+                                if let Some((operation_job, new)) = schedule_operation(operation, config, &mut existing_operation_jobs) {
+                                    binary_heap.push(operation_job);
+                                    if new {
+                                        updated_operations_count += 1;
                                     }
                                 }
+                            }
 
-                                if found {
-                                    continue
-                                }
+                            //insert new operations into operations map
+                            operations.insert(*bucket_key, binary_heap);
+                            operation_bucket_hashes.insert(*bucket_key, proddle::bucket_digest(operation_vec));
+                        }
 
-                                //determine interval
-                                for operation_tag in operation.tags.iter() {
-                                    for (include_tag, interval) in include_tags {
-                                        if operation_tag.eq(*include_tag) && *interval < operation_interval {
-                                            operation_interval = *interval;
+                        //apply changed operations to their buckets and adopt the bridge bucket hashes
+                        if let Some(operation_delta) = response.operation_delta {
+                            for (bucket_key, operation_vec) in operation_delta.operations.iter() {
+                                //take the jobs of changed operations out of the bucket, keeping the rest
+                                let mut existing_operation_jobs: HashMap<(String, String), Vec<OperationJob>> = HashMap::new();
+                                let mut binary_heap = BinaryHeap::new();
+                                if let Some(existing_binary_heap) = operations.remove(bucket_key) {
+                                    for operation_job in existing_binary_heap.into_vec() {
+                                        let changed = operation_vec.iter().any(|x| x.measurement_class == operation_job.operation.measurement_class
+                                            && x.domain == operation_job.operation.domain);
+                                        if changed {
+                                            let key = (operation_job.operation.measurement_class.to_owned(), operation_job.operation.domain.to_owned());
+                                            existing_operation_jobs.entry(key).or_insert(Vec::new()).push(operation_job);
+                                        } else {
+                                            binary_heap.push(operation_job);
                                         }
                                     }
                                 }
 
-                                //check if include tag interval was found
-                                if operation_interval == i64::max_value() {
-                                    continue;
+                                //tombstones remove the operation, other changes reschedule it
+                                for operation in operation_vec.iter().filter(|x| !x.deleted) {
+                                    if let Some((operation_job, new)) = schedule_operation(operation, config, &mut existing_operation_jobs) {
+                                        binary_heap.push(operation_job);
+                                        if new {
+                                            updated_operations_count += 1;
+                                        }
+                                    }
                                 }
 
-                                //add operation
-                                binary_heap.push(OperationJob::new(operation.to_owned(), operation_interval));
-                                updated_operations_count += 1;
+                                operations.insert(*bucket_key, binary_heap);
                             }
 
-                            //insert new operations into operations map
-                            operations.insert(*bucket_key, binary_heap);
-                            operation_bucket_hashes.insert(*bucket_key, hasher.finish());
+                            for (bucket_key, operation_bucket_hash) in operation_delta.operation_bucket_hashes.iter() {
+                                operation_bucket_hashes.insert(*bucket_key, *operation_bucket_hash);
+                            }
                         }
-                        Ok(updated_operations_count)
+
+                        *operations_version = response.operations_version;
+                        Ok((updated_operations_count, response.vantage_config))
                     },
                     None => Err(ProddleError::from("malformed update opertions respose.")),
                 }
@@ -135,3 +260,99 @@ impl Client {
         }
     }
 }
+
+//create a job for the operation if the tag filters include it, reusing an existing job when its
+//schedule is unchanged. returns the job and whether it is newly scheduled
+fn schedule_operation(operation: &Operation, config: &Config, existing_operation_jobs: &mut HashMap<(String, String), Vec<OperationJob>>)
+        -> Option<(OperationJob, bool)> {
+    //validate tags
+    let mut operation_interval = i64::max_value();
+    //check if tag is in exclude tags
+    let mut found = false;
+    for operation_tag in operation.tags.iter() {
+        for exclude_tag in config.exclude_tags.iter() {
+            if operation_tag.eq(exclude_tag) {
+                found = true;
+            }
+        }
+    }
+
+    if found {
+        return None;
+    }
+
+    //determine interval
+    for operation_tag in operation.tags.iter() {
+        for (include_tag, interval) in config.include_tags.iter() {
+            if operation_tag.eq(include_tag) && *interval < operation_interval {
+                operation_interval = *interval;
+            }
+        }
+    }
+
+    //check if include tag interval was found
+    if operation_interval == i64::max_value() {
+        return None;
+    }
+
+    //reuse an existing job if its schedule is unchanged
+    let key = (operation.measurement_class.to_owned(), operation.domain.to_owned());
+    let existing_operation_job = existing_operation_jobs.get_mut(&key).and_then(|operation_jobs| {
+        let index = operation_jobs.iter().position(|x| x.interval == operation_interval
+            && x.max_jitter == config.max_jitter_seconds && x.operation.schedule == operation.schedule
+            && x.operation.start_timestamp == operation.start_timestamp && x.operation.end_timestamp == operation.end_timestamp);
+        index.map(|index| operation_jobs.swap_remove(index))
+    });
+
+    if let Some(mut operation_job) = existing_operation_job {
+        operation_job.operation = operation.to_owned();
+        return Some((operation_job, false));
+    }
+
+    //add operation if its schedule window has not closed
+    match OperationJob::new(operation.to_owned(), operation_interval, &config.hostname, config.max_jitter_seconds) {
+        Ok(Some(operation_job)) => Some((operation_job, true)),
+        Ok(None) => None,
+        Err(e) => {
+            error!("failed to schedule operation for domain '{}': {}", operation.domain, e);
+            None
+        },
+    }
+}
+
+//keep the measurements at the failed indices of the request, a failure outside the buffer is ignored
+fn retain_failures(measurement_buffer: &mut Vec<Document>, measurement_failures: &[usize]) {
+    let mut index = 0;
+    measurement_buffer.retain(|_| {
+        let failed = measurement_failures.contains(&index);
+        index += 1;
+        failed
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurements(count: i64) -> Vec<Document> {
+        (0..count).map(|x| doc!("index" => x)).collect()
+    }
+
+    fn indices(measurement_buffer: &Vec<Document>) -> Vec<i64> {
+        measurement_buffer.iter().map(|x| x.get_i64("index").unwrap()).collect()
+    }
+
+    #[test]
+    fn only_failed_measurements_are_kept() {
+        let mut measurement_buffer = measurements(5);
+        retain_failures(&mut measurement_buffer, &[4, 1]);
+        assert_eq!(indices(&measurement_buffer), vec![1, 4]);
+
+        retain_failures(&mut measurement_buffer, &[]);
+        assert!(measurement_buffer.is_empty());
+
+        let mut measurement_buffer = measurements(2);
+        retain_failures(&mut measurement_buffer, &[0, 1, 7]);
+        assert_eq!(indices(&measurement_buffer), vec![0, 1]);
+    }
+}
@@ -0,0 +1,266 @@
+use clap::ArgMatches;
+use proddle::{ProddleError, VantageConfig};
+use toml;
+
+use executor::OverflowPolicy;
+use rate_limiter::{RateLimit, RateLimiter};
+use retry_policy::{RetryPolicies, RetryPolicy};
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Vantage settings, read either from the command line or from a TOML file whose keys match the
+/// field names (ex. 'bridge_address = "127.0.0.1:12289"' and an '[include_tags]' table mapping
+/// tags to intervals).
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    pub hostname: String,
+    pub ip_address: String,
+    pub groups: Vec<String>,
+    pub bucket_count: u64,
+    pub thread_count: usize,
+    pub max_in_flight: usize,
+    pub queue_capacity: usize,
+    pub overflow_policy: String,
+    pub bridge_address: String,
+    pub bridge_update_interval_seconds: u32,
+    pub send_measurements_interval_seconds: u32,
+    pub heartbeat_interval_seconds: u32,
+    pub max_retries: i32,
+    pub retry_policies: Vec<String>,
+    pub global_rate_limit: Option<String>,
+    pub ip_address_rate_limit: Option<String>,
+    pub domain_rate_limit: Option<String>,
+    pub max_jitter_seconds: i64,
+    pub include_tags: HashMap<String, i64>,
+    pub exclude_tags: Vec<String>,
+    pub spool_file: Option<String>,
+    pub id_file: String,
+    pub metrics_address: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            hostname: String::new(),
+            ip_address: String::new(),
+            groups: Vec::new(),
+            bucket_count: 50,
+            thread_count: 2,
+            max_in_flight: 512,
+            queue_capacity: 256,
+            overflow_policy: String::from("delay"),
+            bridge_address: String::from("127.0.0.1:12289"),
+            bridge_update_interval_seconds: 1440,
+            send_measurements_interval_seconds: 300,
+            heartbeat_interval_seconds: 60,
+            max_retries: 3,
+            retry_policies: Vec::new(),
+            global_rate_limit: None,
+            ip_address_rate_limit: None,
+            domain_rate_limit: None,
+            max_jitter_seconds: 0,
+            include_tags: HashMap::new(),
+            exclude_tags: Vec::new(),
+            spool_file: None,
+            id_file: String::from("vantage.id"),
+            metrics_address: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(matches: &ArgMatches) -> Result<Config, ProddleError> {
+        let bridge_ip_address = try!(matches.value_of("BRIDGE_IP_ADDRESS").ok_or("failed to parse bridge ip address"));
+        let bridge_port = try!(value_t!(matches.value_of("BRIDGE_PORT"), u16));
+        let include_tags = match matches.values_of("INCLUDE_TAGS") {
+            Some(include_tags) => {
+                let mut hash_map = HashMap::new();
+                for include_tag in include_tags {
+                    let mut split_values = include_tag.split("|");
+                    let tag = try!(split_values.nth(0).ok_or("failed to fetch include tag"));
+                    let interval = try!(try!(split_values.nth(0).ok_or("failed to fetch include tag interval")).parse::<i64>());
+
+                    hash_map.insert(tag.to_owned(), interval);
+                }
+
+                hash_map
+            },
+            None => HashMap::new(),
+        };
+
+        let config = Config {
+            hostname: try!(value_t!(matches, "HOSTNAME", String)),
+            ip_address: try!(value_t!(matches, "IP_ADDRESS", String)),
+            groups: match matches.values_of("GROUPS") {
+                Some(groups) => groups.map(|x| x.to_owned()).collect(),
+                None => Vec::new(),
+            },
+            bucket_count: try!(value_t!(matches.value_of("BUCKET_COUNT"), u64)),
+            thread_count: try!(value_t!(matches.value_of("THREAD_COUNT"), usize)),
+            max_in_flight: try!(value_t!(matches.value_of("MAX_IN_FLIGHT"), usize)),
+            queue_capacity: try!(value_t!(matches.value_of("QUEUE_CAPACITY"), usize)),
+            overflow_policy: try!(value_t!(matches.value_of("OVERFLOW_POLICY"), String)),
+            bridge_address: format!("{}:{}", bridge_ip_address, bridge_port),
+            bridge_update_interval_seconds: try!(value_t!(matches.value_of("BRIDGE_UPDATE_INTERVAL_SECONDS"), u32)),
+            send_measurements_interval_seconds: try!(value_t!(matches.value_of("SEND_MEASUREMENTS_INTERVAL_SECONDS"), u32)),
+            heartbeat_interval_seconds: try!(value_t!(matches.value_of("HEARTBEAT_INTERVAL_SECONDS"), u32)),
+            max_retries: try!(value_t!(matches.value_of("MAX_RETRIES"), i32)),
+            retry_policies: match matches.values_of("RETRY_POLICIES") {
+                Some(values) => values.map(|x| x.to_owned()).collect(),
+                None => Vec::new(),
+            },
+            global_rate_limit: matches.value_of("GLOBAL_RATE_LIMIT").map(|x| x.to_owned()),
+            ip_address_rate_limit: matches.value_of("IP_ADDRESS_RATE_LIMIT").map(|x| x.to_owned()),
+            domain_rate_limit: matches.value_of("DOMAIN_RATE_LIMIT").map(|x| x.to_owned()),
+            max_jitter_seconds: try!(value_t!(matches.value_of("MAX_JITTER_SECONDS"), i64)),
+            include_tags: include_tags,
+            exclude_tags: match matches.values_of("EXCLUDE_TAGS") {
+                Some(exclude_tags) => exclude_tags.map(|x| x.to_owned()).collect(),
+                None => Vec::new(),
+            },
+            spool_file: matches.value_of("SPOOL_FILE").map(|x| x.to_owned()),
+            id_file: try!(absolute_path(try!(env::current_dir()), matches.value_of("ID_FILE").unwrap())),
+            metrics_address: matches.value_of("METRICS_ADDRESS").map(|x| x.to_owned()),
+        };
+
+        try!(config.validate());
+        Ok(config)
+    }
+
+    pub fn from_file(path: &str) -> Result<Config, ProddleError> {
+        let mut contents = String::new();
+        let mut file = try!(File::open(path));
+        try!(file.read_to_string(&mut contents));
+
+        let mut config: Config = try!(toml::from_str(&contents).map_err(|e| format!("failed to parse config file '{}': {}", path, e)));
+
+        //the id file is resolved relative to the configuration file rather than the working directory
+        if !config.id_file.is_empty() {
+            let config_directory = try!(env::current_dir()).join(path).parent().map(|x| x.to_path_buf()).unwrap_or_default();
+            config.id_file = try!(absolute_path(config_directory, &config.id_file));
+        }
+
+        try!(config.validate());
+        Ok(config)
+    }
+
+    /// Returns a copy of the configuration with the settings pushed by the bridge applied.
+    pub fn with_vantage_config(&self, vantage_config: &VantageConfig) -> Config {
+        let mut config = self.clone();
+        if let Some(ref include_tags) = vantage_config.include_tags {
+            config.include_tags = include_tags.clone();
+        }
+
+        if let Some(ref exclude_tags) = vantage_config.exclude_tags {
+            config.exclude_tags = exclude_tags.clone();
+        }
+
+        if let Some(max_jitter_seconds) = vantage_config.max_jitter_seconds {
+            config.max_jitter_seconds = max_jitter_seconds;
+        }
+
+        config
+    }
+
+    /// Checks every setting so invalid values are reported when the configuration is loaded
+    /// rather than when they are first used.
+    pub fn validate(&self) -> Result<(), ProddleError> {
+        if !is_valid_hostname(&self.hostname) {
+            return Err(ProddleError::from(format!("invalid hostname '{}'", self.hostname)));
+        }
+
+        if !self.ip_address.is_empty() {
+            try!(IpAddr::from_str(&self.ip_address).map_err(|_| format!("invalid ip address '{}'", self.ip_address)));
+        }
+
+        if self.id_file.is_empty() {
+            return Err(ProddleError::from("id_file must not be empty"));
+        }
+
+        if self.bucket_count == 0 || self.thread_count == 0 || self.max_in_flight == 0 || self.queue_capacity == 0 {
+            return Err(ProddleError::from("bucket_count, thread_count, max_in_flight and queue_capacity must be positive"));
+        }
+
+        if self.bridge_update_interval_seconds == 0 || self.send_measurements_interval_seconds == 0 || self.heartbeat_interval_seconds == 0 {
+            return Err(ProddleError::from("bridge_update_interval_seconds, send_measurements_interval_seconds and heartbeat_interval_seconds must be positive"));
+        }
+
+        if self.max_retries < 1 {
+            return Err(ProddleError::from("max_retries must be positive"));
+        }
+
+        if self.max_jitter_seconds < 0 {
+            return Err(ProddleError::from("max_jitter_seconds must not be negative"));
+        }
+
+        for (tag, interval) in self.include_tags.iter() {
+            if *interval <= 0 {
+                return Err(ProddleError::from(format!("include tag '{}' requires a positive interval", tag)));
+            }
+        }
+
+        try!(self.bridge_address());
+        if let Some(ref metrics_address) = self.metrics_address {
+            try!(SocketAddr::from_str(metrics_address).map_err(|_| format!("invalid metrics address '{}'", metrics_address)));
+        }
+
+        try!(self.overflow_policy());
+        try!(self.retry_policies());
+        try!(self.rate_limiter());
+        Ok(())
+    }
+
+    pub fn bridge_address(&self) -> Result<SocketAddr, ProddleError> {
+        Ok(try!(SocketAddr::from_str(&self.bridge_address)))
+    }
+
+    pub fn overflow_policy(&self) -> Result<OverflowPolicy, ProddleError> {
+        OverflowPolicy::parse(&self.overflow_policy)
+    }
+
+    pub fn retry_policies(&self) -> Result<RetryPolicies, ProddleError> {
+        let mut retry_policies = RetryPolicies::new(RetryPolicy::new(self.max_retries));
+        for value in self.retry_policies.iter() {
+            try!(retry_policies.parse_measurement_class(value));
+        }
+
+        Ok(retry_policies)
+    }
+
+    pub fn rate_limiter(&self) -> Result<RateLimiter, ProddleError> {
+        let mut rate_limits = Vec::new();
+        for value in [&self.global_rate_limit, &self.ip_address_rate_limit, &self.domain_rate_limit].iter() {
+            rate_limits.push(match **value {
+                Some(ref value) => Some(try!(RateLimit::parse(value))),
+                None => None,
+            });
+        }
+
+        Ok(RateLimiter::new(rate_limits[0], rate_limits[1], rate_limits[2]))
+    }
+}
+
+//hostnames follow rfc 1123, dot separated labels of letters, digits and hyphens
+fn is_valid_hostname(hostname: &str) -> bool {
+    let hostname = hostname.trim_end_matches('.');
+    !hostname.is_empty() && hostname.len() <= 253 && hostname.split('.').all(|label| {
+        label.len() >= 1 && label.len() <= 63 && !label.starts_with('-') && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+//resolve a relative path against 'directory'
+fn absolute_path<P: AsRef<Path>>(directory: P, path: &str) -> Result<String, ProddleError> {
+    let path = directory.as_ref().join(path);
+    match path.to_str() {
+        Some(path) => Ok(path.to_owned()),
+        None => Err(ProddleError::from(format!("path '{}' is not valid unicode", path.display()))),
+    }
+}
@@ -1,94 +1,854 @@
-use bson::Document;
-use chan::{self, Sender};
-use proddle::ProddleError;
-use rand::{self, Rng};
+use bson::{Bson, Document};
+use chan::Sender;
+use curl::multi::{Easy2Handle, Multi};
+use curl::easy::Easy2;
+use proddle::{self, Operation, ProddleError};
 use time;
 
-use measurement;
+use config::Config;
+use measurement::http_get::{self, HttpGet};
 use operation_job::OperationJob;
+use rate_limiter::RateLimiter;
+use retry_policy::{ErrorCategory, RetryPolicies, RetryPolicy};
 
 use std;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+//seconds past its scheduled time after which an execution is counted as late
+static LATE_THRESHOLD_SECONDS: i64 = 30;
+//milliseconds a driver waits on socket activity before checking for new work
+static DRIVER_WAIT_MILLISECONDS: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    DropOldest,
+    SkipIfRunning,
+    Delay,
+}
+
+impl OverflowPolicy {
+    pub fn parse(value: &str) -> Result<OverflowPolicy, ProddleError> {
+        match value {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "skip-if-running" => Ok(OverflowPolicy::SkipIfRunning),
+            "delay" => Ok(OverflowPolicy::Delay),
+            _ => Err(ProddleError::from(format!("unknown overflow policy '{}'", value))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ExecutorStatistics {
+    pub executed: i64,
+    pub dropped: i64,
+    pub skipped: i64,
+    pub delayed: i64,
+    pub missed: i64,
+    pub late: i64,
+    pub retried: i64,
+    pub succeeded: i64,
+    pub errors: HashMap<String, i64>,
+}
+
+impl ExecutorStatistics {
+    pub fn merge(&mut self, other: &ExecutorStatistics) {
+        self.executed += other.executed;
+        self.dropped += other.dropped;
+        self.skipped += other.skipped;
+        self.delayed += other.delayed;
+        self.missed += other.missed;
+        self.late += other.late;
+        self.retried += other.retried;
+        self.succeeded += other.succeeded;
+        for (error_category, count) in other.errors.iter() {
+            *self.errors.entry(error_category.to_owned()).or_insert(0) += *count;
+        }
+    }
+
+    pub fn to_document(&self) -> Document {
+        let mut errors = Document::new();
+        for (error_category, count) in self.errors.iter() {
+            errors.insert_bson(error_category.to_owned(), Bson::I64(*count));
+        }
+
+        doc!(
+            "executed" => self.executed,
+            "dropped" => self.dropped,
+            "skipped" => self.skipped,
+            "delayed" => self.delayed,
+            "missed" => self.missed,
+            "late" => self.late,
+            "retried" => self.retried,
+            "succeeded" => self.succeeded,
+            "errors" => errors
+        )
+    }
+}
+
+/// Snapshot of executor load, in flight counts transfers currently in progress.
+#[derive(Clone, Debug, Default)]
+pub struct ExecutorStatus {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub in_flight: usize,
+    pub in_flight_capacity: usize,
+}
+
+struct WorkQueue {
+    operation_jobs: VecDeque<OperationJob>,
+    active_operations: HashMap<String, usize>,
+    statistics: ExecutorStatistics,
+}
+
+impl WorkQueue {
+    fn pop(&mut self) -> Option<OperationJob> {
+        let operation_job = match self.operation_jobs.pop_front() {
+            Some(operation_job) => operation_job,
+            None => return None,
+        };
+
+        let now = time::now_utc().to_timespec().sec;
+        if now - operation_job.scheduled_time > operation_job.max_jitter + LATE_THRESHOLD_SECONDS {
+            self.statistics.late += 1;
+        }
+
+        self.statistics.executed += 1;
+        Some(operation_job)
+    }
+
+    fn push(&mut self, operation_job: OperationJob) {
+        *self.active_operations.entry(operation_key(&operation_job.operation)).or_insert(0) += 1;
+        self.operation_jobs.push_back(operation_job);
+    }
+
+    fn release(&mut self, operation: &Operation) {
+        let key = operation_key(operation);
+        let remove = match self.active_operations.get_mut(&key) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            },
+            None => false,
+        };
+
+        if remove {
+            self.active_operations.remove(&key);
+        }
+    }
+}
+
+//settings shared with driver threads which may change when the configuration is reloaded
+struct DriverSettings {
+    vantage_id: String,
+    thread_count: usize,
+    max_in_flight: usize,
+    hostname: String,
+    ip_address: String,
+    local_ip_addresses: Vec<String>,
+    retry_policies: RetryPolicies,
+    shutdown: bool,
+}
 
 pub struct Executor {
-    operation_tx: Sender<OperationJob>,
+    work_queue: Arc<(Mutex<WorkQueue>, Condvar)>,
+    driver_settings: Arc<RwLock<DriverSettings>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    measurement_tx: Sender<Document>,
+    in_flight: Arc<AtomicUsize>,
+    drivers: Vec<JoinHandle<()>>,
+    driver_count: usize,
+    capacity: usize,
+    overflow_policy: OverflowPolicy,
+    config: Config,
 }
 
 impl Executor {
-    pub fn new(thread_count: usize, hostname: &str, ip_address: &str, max_retries: i32, measurement_tx: Sender<Document>) -> Executor {
-        let (operation_tx, operation_rx) = chan::sync(0);
-        for _ in 0..thread_count {
-            let thread_operation_rx = operation_rx.clone();
-            let (t_hostname, t_ip_address, t_measurement_tx) = (hostname.to_owned(), ip_address.to_owned(), measurement_tx.clone());
-            let _ = std::thread::spawn(move || {
-                loop {
-                    chan_select! {
-                        thread_operation_rx.recv() -> operation_job => {
-                            match operation_job {
-                                Some(operation_job) => {
-                                    if let Err(e) = execute_measurement(operation_job, &t_hostname, &t_ip_address, max_retries, t_measurement_tx.clone()) {
-                                        error!("{}", e);
-                                    }
-                                },
-                                None => {
-                                    warn!("executor thread recv 'None' operation job");
-                                },
-                            }
-                        },
+    /// Starts 'thread_count' driver threads, each keeping up to 'max_in_flight' measurements in
+    /// progress concurrently.
+    pub fn new(config: &Config, vantage_id: &str, measurement_tx: Sender<Document>) -> Result<Executor, ProddleError> {
+        let work_queue = Arc::new((
+            Mutex::new(WorkQueue {
+                operation_jobs: VecDeque::with_capacity(config.queue_capacity),
+                active_operations: HashMap::new(),
+                statistics: ExecutorStatistics::default(),
+            }),
+            Condvar::new()
+        ));
+
+        let driver_settings = DriverSettings {
+            vantage_id: vantage_id.to_owned(),
+            thread_count: config.thread_count,
+            max_in_flight: config.max_in_flight,
+            hostname: config.hostname.to_owned(),
+            ip_address: config.ip_address.to_owned(),
+            local_ip_addresses: Vec::new(),
+            retry_policies: try!(config.retry_policies()),
+            shutdown: false,
+        };
+
+        let mut executor = Executor {
+            work_queue: work_queue,
+            driver_settings: Arc::new(RwLock::new(driver_settings)),
+            rate_limiter: Arc::new(Mutex::new(try!(config.rate_limiter()))),
+            measurement_tx: measurement_tx,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            drivers: Vec::new(),
+            driver_count: 0,
+            capacity: config.queue_capacity,
+            overflow_policy: try!(config.overflow_policy()),
+            config: config.clone(),
+        };
+
+        executor.spawn_drivers();
+        Ok(executor)
+    }
+
+    /// Applies a reloaded configuration. Queued and in flight executions are kept, surplus
+    /// drivers exit once their in flight measurements complete.
+    pub fn reconfigure(&mut self, config: &Config) -> Result<(), ProddleError> {
+        let (retry_policies, overflow_policy) = (try!(config.retry_policies()), try!(config.overflow_policy()));
+        if config.global_rate_limit != self.config.global_rate_limit || config.ip_address_rate_limit != self.config.ip_address_rate_limit
+                || config.domain_rate_limit != self.config.domain_rate_limit {
+            *self.rate_limiter.lock().unwrap() = try!(config.rate_limiter());
+        }
+
+        {
+            let mut driver_settings = self.driver_settings.write().unwrap();
+            driver_settings.thread_count = config.thread_count;
+            driver_settings.max_in_flight = config.max_in_flight;
+            driver_settings.hostname = config.hostname.to_owned();
+            driver_settings.retry_policies = retry_policies;
+        }
+
+        self.capacity = config.queue_capacity;
+        self.overflow_policy = overflow_policy;
+        self.config = config.clone();
+
+        //wake idle drivers so surplus ones exit and start new drivers if necessary
+        self.work_queue.1.notify_all();
+        if config.thread_count < self.driver_count {
+            self.driver_count = config.thread_count;
+        }
+
+        self.spawn_drivers();
+        Ok(())
+    }
+
+    fn spawn_drivers(&mut self) {
+        let thread_count = self.driver_settings.read().unwrap().thread_count;
+        while self.driver_count < thread_count {
+            let index = self.driver_count;
+            let (t_work_queue, t_driver_settings) = (self.work_queue.clone(), self.driver_settings.clone());
+            let (t_rate_limiter, t_measurement_tx) = (self.rate_limiter.clone(), self.measurement_tx.clone());
+            let t_in_flight = self.in_flight.clone();
+            let handle = std::thread::spawn(move || {
+                let mut driver = Driver {
+                    index: index,
+                    work_queue: t_work_queue,
+                    driver_settings: t_driver_settings,
+                    multi: Multi::new(),
+                    transfers: HashMap::new(),
+                    pending_probes: BinaryHeap::new(),
+                    next_token: 0,
+                    rate_limiter: t_rate_limiter,
+                    measurement_tx: t_measurement_tx,
+                    in_flight: t_in_flight,
+                };
+
+                driver.run();
+            });
+
+            self.drivers.push(handle);
+            self.driver_count += 1;
+        }
+    }
+
+    /// Stops executing operations, discarding queued jobs and pending retries, and blocks until
+    /// every in flight measurement has completed and been handed to the measurement channel.
+    /// Returns the statistics gathered since they were last taken.
+    pub fn shutdown(mut self) -> ExecutorStatistics {
+        self.driver_settings.write().unwrap().shutdown = true;
+        {
+            let &(ref lock, ref condvar) = &*self.work_queue;
+            let mut work_queue = lock.lock().unwrap();
+            let discarded_count = work_queue.operation_jobs.len() as i64;
+            work_queue.operation_jobs.clear();
+            work_queue.statistics.dropped += discarded_count;
+            work_queue.statistics.missed += discarded_count;
+            condvar.notify_all();
+        }
+
+        for handle in self.drivers.drain(..) {
+            if let Err(_) = handle.join() {
+                error!("executor driver panicked during shutdown");
+            }
+        }
+
+        self.take_statistics()
+    }
+
+    /// Sets the vantage address stamped on measurements along with the local interface addresses.
+    pub fn set_ip_addresses(&mut self, ip_address: String, local_ip_addresses: Vec<String>) {
+        let mut driver_settings = self.driver_settings.write().unwrap();
+        driver_settings.ip_address = ip_address;
+        driver_settings.local_ip_addresses = local_ip_addresses;
+    }
+
+    /// Queues the operation job without blocking. Under the 'Delay' overflow policy a job that
+    /// does not fit is handed back so the scheduler can retry it later.
+    pub fn execute_operation(&mut self, operation_job: OperationJob) -> Result<Option<OperationJob>, ProddleError> {
+        let &(ref lock, ref condvar) = &*self.work_queue;
+        let mut work_queue = try!(lock.lock().map_err(|_| "executor work queue lock poisoned"));
+
+        match self.overflow_policy {
+            OverflowPolicy::DropOldest => {
+                if work_queue.operation_jobs.len() >= self.capacity {
+                    if let Some(dropped_job) = work_queue.operation_jobs.pop_front() {
+                        work_queue.release(&dropped_job.operation);
+                        work_queue.statistics.dropped += 1;
+                        work_queue.statistics.missed += 1;
                     }
                 }
-            });
+            },
+            OverflowPolicy::SkipIfRunning => {
+                if work_queue.active_operations.contains_key(&operation_key(&operation_job.operation))
+                        || work_queue.operation_jobs.len() >= self.capacity {
+                    work_queue.statistics.skipped += 1;
+                    work_queue.statistics.missed += 1;
+                    return Ok(None);
+                }
+            },
+            OverflowPolicy::Delay => {
+                if work_queue.operation_jobs.len() >= self.capacity {
+                    work_queue.statistics.delayed += 1;
+                    return Ok(Some(operation_job));
+                }
+            },
         }
 
-        Executor {
-            operation_tx: operation_tx,
+        work_queue.push(operation_job);
+        condvar.notify_one();
+        Ok(None)
+    }
+
+    /// Records a scheduled execution the scheduler passed over without dispatching.
+    pub fn record_missed(&mut self) {
+        let &(ref lock, _) = &*self.work_queue;
+        if let Ok(mut work_queue) = lock.lock() {
+            work_queue.statistics.missed += 1;
         }
     }
 
-    pub fn execute_operation(&mut self, operation_job: OperationJob) -> Result<(), ProddleError> {
-        self.operation_tx.send(operation_job);
-        Ok(())
+    /// Returns the statistics gathered since the previous call and resets them.
+    pub fn take_statistics(&mut self) -> ExecutorStatistics {
+        let &(ref lock, _) = &*self.work_queue;
+        let mut work_queue = lock.lock().unwrap();
+        std::mem::replace(&mut work_queue.statistics, ExecutorStatistics::default())
+    }
+
+    pub fn status(&self) -> ExecutorStatus {
+        let queue_depth = self.work_queue.0.lock().unwrap().operation_jobs.len();
+        let driver_settings = self.driver_settings.read().unwrap();
+        ExecutorStatus {
+            queue_depth: queue_depth,
+            queue_capacity: self.capacity,
+            in_flight: self.in_flight.load(AtomicOrdering::SeqCst),
+            in_flight_capacity: driver_settings.thread_count * driver_settings.max_in_flight,
+        }
+    }
+}
+
+fn operation_key(operation: &Operation) -> String {
+    format!("{}|{}", operation.measurement_class, operation.domain)
+}
+
+fn as_seconds(duration: &Duration) -> f64 {
+    duration.as_secs() as f64 + (duration.subsec_nanos() as f64 / 1000000000.0)
+}
+
+//a single measurement execution, including its retries, tracked by a driver
+struct Probe {
+    operation_job: OperationJob,
+    parameters: HashMap<String, String>,
+    retry_policy: RetryPolicy,
+    attempt: i32,
+    retry_delay: f64,
+    rate_limit_delay: f64,
+    prefix_index: usize,
+    start_timestamp_milliseconds: i64,
+    internal_error_message: Option<String>,
+}
+
+impl Probe {
+    fn new(operation_job: OperationJob, retry_policies: &RetryPolicies) -> Probe {
+        //create measurement arguments
+        let mut parameters = HashMap::new();
+        for operation_parameter in operation_job.operation.parameters.iter() {
+            parameters.insert(operation_parameter.name.to_owned(), operation_parameter.value.to_owned());
+        }
+
+        //resolve retry policy for measurement class and operation
+        let retry_policy = retry_policies.get(&operation_job.operation.measurement_class);
+        let retry_policy = match retry_policy.with_overrides(&parameters) {
+            Ok(retry_policy) => retry_policy,
+            Err(e) => {
+                warn!("ignoring retry policy overrides for domain '{}': {}", operation_job.operation.domain, e);
+                retry_policy.clone()
+            },
+        };
+
+        Probe {
+            operation_job: operation_job,
+            parameters: parameters,
+            retry_policy: retry_policy,
+            attempt: 0,
+            retry_delay: 0.0,
+            rate_limit_delay: 0.0,
+            prefix_index: 0,
+            start_timestamp_milliseconds: 0,
+            internal_error_message: None,
+        }
+    }
+}
+
+//a probe waiting to start its next attempt
+struct PendingProbe {
+    start_time: Instant,
+    probe: Probe,
+}
+
+impl PartialEq for PendingProbe {
+    fn eq(&self, other: &PendingProbe) -> bool {
+        self.start_time == other.start_time
+    }
+}
+
+impl Eq for PendingProbe {}
+
+impl Ord for PendingProbe {
+    fn cmp(&self, other: &PendingProbe) -> Ordering {
+        other.start_time.cmp(&self.start_time)
+    }
+}
+
+impl PartialOrd for PendingProbe {
+    fn partial_cmp(&self, other: &PendingProbe) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-fn execute_measurement(operation_job: OperationJob, hostname: &str, ip_address: &str, max_retries: i32, tx: Sender<Document>) -> Result<(), ProddleError> {
-    //create measurement arguments
-    let mut parameters = HashMap::new();
-    for operation_parameter in operation_job.operation.parameters {
-        parameters.insert(operation_parameter.name, operation_parameter.value);
+//drives many concurrent transfers on a single thread using the curl multi interface
+struct Driver {
+    index: usize,
+    work_queue: Arc<(Mutex<WorkQueue>, Condvar)>,
+    driver_settings: Arc<RwLock<DriverSettings>>,
+    multi: Multi,
+    transfers: HashMap<usize, (Easy2Handle<HttpGet>, Probe)>,
+    pending_probes: BinaryHeap<PendingProbe>,
+    next_token: usize,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    measurement_tx: Sender<Document>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Driver {
+    fn run(&mut self) {
+        let mut draining = false;
+        loop {
+            //a driver beyond the configured thread count stops taking work and exits once idle
+            let (thread_count, max_in_flight, shutdown) = {
+                let driver_settings = self.driver_settings.read().unwrap();
+                (driver_settings.thread_count, driver_settings.max_in_flight, driver_settings.shutdown)
+            };
+
+            //on shutdown probes waiting on a retry or rate limit are abandoned, their previous
+            //attempts have already been recorded
+            if shutdown && !self.pending_probes.is_empty() {
+                info!("driver {} abandoning {} pending probe(s) on shutdown", self.index, self.pending_probes.len());
+                let &(ref lock, _) = &*self.work_queue;
+                let mut work_queue = lock.lock().unwrap();
+                for pending_probe in self.pending_probes.drain() {
+                    work_queue.release(&pending_probe.probe.operation_job.operation);
+                }
+            }
+
+            draining = draining || shutdown || self.index >= thread_count;
+            if draining && self.transfers.is_empty() && self.pending_probes.is_empty() {
+                break;
+            }
+
+            //start probes until the in flight limit is reached
+            while !draining && self.transfers.len() < max_in_flight {
+                match self.next_probe() {
+                    Some(probe) => self.start(probe),
+                    None => break,
+                }
+            }
+
+            if self.transfers.is_empty() {
+                self.wait_for_work();
+                continue;
+            }
+
+            //progress transfers and handle completed ones
+            if let Err(e) = self.multi.perform() {
+                error!("failed to perform transfers: {}", e);
+            }
+
+            let mut completed = Vec::new();
+            self.multi.messages(|message| {
+                if let (Ok(token), Some(result)) = (message.token(), message.result()) {
+                    completed.push((token, result));
+                }
+            });
+
+            for (token, result) in completed {
+                if let Err(e) = self.finish_transfer(token, result) {
+                    error!("{}", e);
+                }
+            }
+
+            if let Err(e) = self.multi.wait(&mut [], Duration::from_millis(DRIVER_WAIT_MILLISECONDS)) {
+                error!("failed to wait on transfers: {}", e);
+            }
+        }
+    }
+
+    //retrieve a pending probe that is ready to start or a new operation job from the work queue
+    fn next_probe(&mut self) -> Option<Probe> {
+        let ready = match self.pending_probes.peek() {
+            Some(pending_probe) => pending_probe.start_time <= Instant::now(),
+            None => false,
+        };
+
+        if ready {
+            return self.pending_probes.pop().map(|pending_probe| pending_probe.probe);
+        }
+
+        let &(ref lock, _) = &*self.work_queue;
+        let operation_job = lock.lock().unwrap().pop();
+        let driver_settings = self.driver_settings.read().unwrap();
+        operation_job.map(|operation_job| Probe::new(operation_job, &driver_settings.retry_policies))
+    }
+
+    //block until new work is queued or the next pending probe is ready to start
+    fn wait_for_work(&mut self) {
+        let timeout = match self.pending_probes.peek() {
+            Some(pending_probe) => {
+                let now = Instant::now();
+                match pending_probe.start_time > now {
+                    true => pending_probe.start_time - now,
+                    false => return,
+                }
+            },
+            None => Duration::new(1, 0),
+        };
+
+        let &(ref lock, ref condvar) = &*self.work_queue;
+        let work_queue = lock.lock().unwrap();
+        if work_queue.operation_jobs.is_empty() {
+            let _ = condvar.wait_timeout(work_queue, timeout);
+        }
+    }
+
+    fn start(&mut self, mut probe: Probe) {
+        if probe.prefix_index == 0 {
+            //delay the attempt until every applicable rate limit allows it
+            let wait = self.rate_limiter.lock().unwrap().acquire(&probe.operation_job.operation.domain);
+            if let Some(wait) = wait {
+                probe.rate_limit_delay += as_seconds(&wait);
+                self.pending_probes.push(PendingProbe {
+                    start_time: Instant::now() + wait,
+                    probe: probe,
+                });
+                return;
+            }
+
+            probe.start_timestamp_milliseconds = proddle::timestamp_milliseconds();
+        }
+
+        //attempt each remaining prefix until a transfer is started
+        while probe.prefix_index < http_get::PREFIXES.len() {
+            probe.internal_error_message = None;
+            match self.add_transfer(&probe) {
+                Ok((token, handle)) => {
+                    self.transfers.insert(token, (handle, probe));
+                    self.in_flight.fetch_add(1, AtomicOrdering::SeqCst);
+                    return;
+                },
+                Err(e) => {
+                    probe.internal_error_message = Some(format!("{}", e));
+                    probe.prefix_index += 1;
+                },
+            }
+        }
+
+        self.complete(probe, None, None);
+    }
+
+    fn add_transfer(&mut self, probe: &Probe) -> Result<(usize, Easy2Handle<HttpGet>), ProddleError> {
+        let operation = &probe.operation_job.operation;
+        let easy = match operation.measurement_class.as_ref() {
+            "HttpGet" => try!(http_get::create(&operation.domain, probe.prefix_index, &probe.parameters)),
+            _ => return Err(ProddleError::from(format!("Unknown measurement class '{}'.", operation.measurement_class))),
+        };
+
+        let mut handle = try!(self.multi.add2(easy));
+        let token = self.next_token;
+        self.next_token = self.next_token.wrapping_add(1);
+        try!(handle.set_token(token));
+        Ok((token, handle))
     }
 
-    for i in 0..max_retries {
-        //execute measurement
-        let timestamp = time::now_utc().to_timespec().sec;
-        let mut document = match operation_job.operation.measurement_class.as_ref() {
-            "HttpGet" => measurement::http_get::execute(&operation_job.operation.domain, &parameters)?,
-            _ => return Err(ProddleError::from(format!("Unknown measurement class '{}'.", operation_job.operation.measurement_class))),
+    fn finish_transfer(&mut self, token: usize, result: Result<(), ::curl::Error>) -> Result<(), ProddleError> {
+        let (handle, mut probe) = try!(self.transfers.remove(&token).ok_or("completed transfer not found"));
+        self.in_flight.fetch_sub(1, AtomicOrdering::SeqCst);
+        let mut easy = try!(self.multi.remove2(handle));
+        if let Ok(Some(ip_address)) = easy.primary_ip() {
+            self.rate_limiter.lock().unwrap().record_ip_address(&probe.operation_job.operation.domain, ip_address);
+        }
+
+        match result {
+            Ok(_) => self.complete(probe, Some(easy), None),
+            Err(e) => {
+                //attempt the next prefix before recording a measurement error
+                probe.prefix_index += 1;
+                match probe.prefix_index < http_get::PREFIXES.len() {
+                    true => self.start(probe),
+                    false => self.complete(probe, Some(easy), Some((format!("{}", e), ErrorCategory::from_curl_error(&e)))),
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn complete(&mut self, mut probe: Probe, mut easy: Option<Easy2<HttpGet>>, measurement_error: Option<(String, ErrorCategory)>) {
+        let internal_error_message = probe.internal_error_message.take();
+        let internal_error = internal_error_message.is_some();
+        let (measurement_error_message, error_category) = match measurement_error {
+            Some((measurement_error_message, error_category)) => (Some(measurement_error_message), Some(error_category)),
+            None => (None, None),
         };
 
-        document.insert_bson(String::from("timestamp"), bson!(timestamp));
-        document.insert_bson(String::from("vantage_hostname"), bson!(hostname));
-        document.insert_bson(String::from("vantage_ip_address"), bson!(ip_address));
-        document.insert_bson(String::from("measurement_class"), bson!(&operation_job.operation.measurement_class));
-        document.insert_bson(String::from("measurement_domain"), bson!(&operation_job.operation.domain));
-
-        //check for errors and handle if necessary
-        if document.contains_key("internal_error_message") {
-            //if internal error - send document and break
-            tx.send(document);
-            break;
-        } else if document.contains_key("measurement_error_message") {
-            //if measurement error - send document and try again
-            document.insert_bson(String::from("remaining_attempts"), bson!(max_retries - 1 - i));
-            tx.send(document);
+        let mut document = http_get::to_document(easy.as_mut(), internal_error_message, measurement_error_message);
+
+        //timestamps are taken on the vantage clock, the bridge adds clock corrected copies
+        document.insert_bson(String::from("timestamp"), bson!(probe.start_timestamp_milliseconds / 1000));
+        document.insert_bson(String::from("start_timestamp_milliseconds"), bson!(probe.start_timestamp_milliseconds));
+        document.insert_bson(String::from("end_timestamp_milliseconds"), bson!(proddle::timestamp_milliseconds()));
+        {
+            let driver_settings = self.driver_settings.read().unwrap();
+            document.insert_bson(String::from("vantage_id"), bson!(&driver_settings.vantage_id));
+            document.insert_bson(String::from("vantage_hostname"), bson!(&driver_settings.hostname));
+            document.insert_bson(String::from("vantage_ip_address"), bson!(&driver_settings.ip_address));
+            document.insert_bson(String::from("vantage_local_ip_addresses"),
+                Bson::Array(driver_settings.local_ip_addresses.iter().map(|x| Bson::String(x.to_owned())).collect()));
+        }
+
+        let operation = &probe.operation_job.operation;
+        document.insert_bson(String::from("measurement_class"), bson!(&operation.measurement_class));
+        document.insert_bson(String::from("measurement_domain"), bson!(&operation.domain));
+        document.insert_bson(String::from("attempt"), bson!(probe.attempt));
+        document.insert_bson(String::from("retry_delay"), bson!(probe.retry_delay));
+        document.insert_bson(String::from("rate_limit_delay"), bson!(probe.rate_limit_delay));
+
+        //internal errors are never retried, measurement errors according to the retry policy
+        let retry = match error_category {
+            Some(error_category) if !internal_error => {
+                document.insert_bson(String::from("error_category"), bson!(error_category.as_str()));
+                document.insert_bson(String::from("remaining_attempts"), bson!(probe.retry_policy.max_attempts - 1 - probe.attempt));
+                probe.retry_policy.should_retry(probe.attempt, error_category)
+            },
+            _ => false,
+        };
+
+        self.measurement_tx.send(document);
+
+        let &(ref lock, _) = &*self.work_queue;
+        let mut work_queue = lock.lock().unwrap();
+        match (internal_error, error_category) {
+            (true, _) => *work_queue.statistics.errors.entry(String::from("internal")).or_insert(0) += 1,
+            (false, Some(error_category)) => *work_queue.statistics.errors.entry(error_category.as_str().to_owned()).or_insert(0) += 1,
+            (false, None) => work_queue.statistics.succeeded += 1,
+        }
+
+        if retry {
+            work_queue.statistics.retried += 1;
+            let delay = probe.retry_policy.delay(probe.attempt);
+            probe.attempt += 1;
+            probe.retry_delay = as_seconds(&delay);
+            probe.rate_limit_delay = 0.0;
+            probe.prefix_index = 0;
+            self.pending_probes.push(PendingProbe {
+                start_time: Instant::now() + delay,
+                probe: probe,
+            });
         } else {
-            //if no error - send document and break
-            tx.send(document);
-            break;
+            work_queue.release(&probe.operation_job.operation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chan;
+    use proddle::Parameter;
+
+    fn operation_job(domain: &str) -> OperationJob {
+        let operation = Operation {
+            version: 1,
+            deleted: false,
+            timestamp: 1500000000,
+            measurement_class: String::from("HttpGet"),
+            domain: domain.to_owned(),
+            parameters: vec![Parameter { name: String::from("timeout"), value: String::from("30") }],
+            tags: vec![String::from("top-sites")],
+            schedule: None,
+            start_timestamp: None,
+            end_timestamp: None,
+        };
+
+        OperationJob::new(operation, 300, "vantage", 0).unwrap().unwrap()
+    }
+
+    //without driver threads queued jobs stay queued until the test pops them
+    fn executor(queue_capacity: usize, overflow_policy: &str) -> Executor {
+        let mut config = Config::default();
+        config.thread_count = 0;
+        config.queue_capacity = queue_capacity;
+        config.overflow_policy = overflow_policy.to_owned();
+
+        let (measurement_tx, _) = chan::async();
+        Executor::new(&config, "vantage-id", measurement_tx).unwrap()
+    }
+
+    fn queued_domains(executor: &Executor) -> Vec<String> {
+        let work_queue = executor.work_queue.0.lock().unwrap();
+        work_queue.operation_jobs.iter().map(|x| x.operation.domain.to_owned()).collect()
+    }
+
+    fn pop(executor: &Executor) -> Option<OperationJob> {
+        executor.work_queue.0.lock().unwrap().pop()
+    }
+
+    #[test]
+    fn drop_oldest_replaces_the_oldest_queued_job() {
+        let mut executor = executor(2, "drop-oldest");
+        for domain in ["a.com", "b.com", "c.com"].iter() {
+            assert!(executor.execute_operation(operation_job(domain)).unwrap().is_none());
         }
 
-        std::thread::sleep(std::time::Duration::new(rand::thread_rng().gen_range(10, 20), 0))
+        assert_eq!(queued_domains(&executor), vec!["b.com", "c.com"]);
+        assert!(!executor.work_queue.0.lock().unwrap().active_operations.contains_key("HttpGet|a.com"));
+
+        let statistics = executor.take_statistics();
+        assert_eq!((statistics.dropped, statistics.missed), (1, 1));
     }
 
-    Ok(())
+    #[test]
+    fn skip_if_running_skips_active_operations_and_full_queues() {
+        let mut executor = executor(2, "skip-if-running");
+        for domain in ["a.com", "a.com", "b.com", "c.com"].iter() {
+            assert!(executor.execute_operation(operation_job(domain)).unwrap().is_none());
+        }
+
+        assert_eq!(queued_domains(&executor), vec!["a.com", "b.com"]);
+
+        //a popped job stays active until its execution is released
+        let operation_job_a = pop(&executor).unwrap();
+        assert!(executor.execute_operation(operation_job("a.com")).unwrap().is_none());
+        assert_eq!(queued_domains(&executor), vec!["b.com"]);
+
+        executor.work_queue.0.lock().unwrap().release(&operation_job_a.operation);
+        assert!(executor.execute_operation(operation_job("a.com")).unwrap().is_none());
+        assert_eq!(queued_domains(&executor), vec!["b.com", "a.com"]);
+
+        let statistics = executor.take_statistics();
+        assert_eq!((statistics.skipped, statistics.missed, statistics.executed), (3, 3, 1));
+    }
+
+    #[test]
+    fn delay_hands_back_jobs_which_do_not_fit() {
+        let mut executor = executor(1, "delay");
+        assert!(executor.execute_operation(operation_job("a.com")).unwrap().is_none());
+        let delayed_job = executor.execute_operation(operation_job("b.com")).unwrap().unwrap();
+        assert_eq!(delayed_job.operation.domain, "b.com");
+        assert_eq!(queued_domains(&executor), vec!["a.com"]);
+
+        //the delayed job fits once the queue has drained
+        assert_eq!(pop(&executor).unwrap().operation.domain, "a.com");
+        assert!(executor.execute_operation(delayed_job).unwrap().is_none());
+        assert_eq!(queued_domains(&executor), vec!["b.com"]);
+
+        let statistics = executor.take_statistics();
+        assert_eq!((statistics.delayed, statistics.missed, statistics.executed), (1, 0, 1));
+    }
+
+    #[test]
+    fn pop_counts_late_executions() {
+        let executor = executor(2, "delay");
+        let mut late_job = operation_job("a.com");
+        late_job.scheduled_time = time::now_utc().to_timespec().sec - LATE_THRESHOLD_SECONDS - 60;
+        executor.work_queue.0.lock().unwrap().push(late_job);
+        executor.work_queue.0.lock().unwrap().push(operation_job("b.com"));
+
+        assert_eq!(pop(&executor).unwrap().operation.domain, "a.com");
+        assert_eq!(pop(&executor).unwrap().operation.domain, "b.com");
+        assert!(pop(&executor).is_none());
+
+        let statistics = executor.work_queue.0.lock().unwrap().statistics.clone();
+        assert_eq!((statistics.executed, statistics.late), (2, 1));
+    }
+
+    #[test]
+    fn reconfigure_applies_capacity_and_overflow_policy() {
+        let mut executor = executor(1, "delay");
+        assert!(executor.execute_operation(operation_job("a.com")).unwrap().is_none());
+        assert!(executor.execute_operation(operation_job("b.com")).unwrap().is_some());
+
+        let mut config = executor.config.clone();
+        config.queue_capacity = 2;
+        config.overflow_policy = String::from("drop-oldest");
+        executor.reconfigure(&config).unwrap();
+        for domain in ["b.com", "c.com"].iter() {
+            assert!(executor.execute_operation(operation_job(domain)).unwrap().is_none());
+        }
+
+        assert_eq!(queued_domains(&executor), vec!["b.com", "c.com"]);
+        assert_eq!(executor.status().queue_capacity, 2);
+    }
+
+    #[test]
+    fn shutdown_discards_queued_jobs() {
+        let mut executor = executor(4, "delay");
+        for domain in ["a.com", "b.com", "c.com"].iter() {
+            assert!(executor.execute_operation(operation_job(domain)).unwrap().is_none());
+        }
+
+        let statistics = executor.shutdown();
+        assert_eq!((statistics.dropped, statistics.missed, statistics.executed), (3, 3, 0));
+    }
+
+    #[test]
+    fn pending_probes_start_earliest_first() {
+        let retry_policies = Config::default().retry_policies().unwrap();
+        let now = Instant::now();
+        let mut pending_probes = BinaryHeap::new();
+        for &(domain, delay_seconds) in [("b.com", 2), ("c.com", 3), ("a.com", 1)].iter() {
+            pending_probes.push(PendingProbe {
+                start_time: now + Duration::from_secs(delay_seconds),
+                probe: Probe::new(operation_job(domain), &retry_policies),
+            });
+        }
+
+        let domains: Vec<String> = (0..3).map(|_| pending_probes.pop().unwrap().probe.operation_job.operation.domain).collect();
+        assert_eq!(domains, vec!["a.com", "b.com", "c.com"]);
+    }
 }
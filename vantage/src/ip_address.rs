@@ -0,0 +1,97 @@
+use get_if_addrs;
+use proddle::ProddleError;
+
+use std::net::IpAddr;
+
+/// Enumerates ipv4 and ipv6 addresses of local interfaces, excluding loopback, link local and
+/// unspecified addresses which are never the source of a measurement.
+pub fn local_ip_addresses() -> Result<Vec<IpAddr>, ProddleError> {
+    let mut ip_addresses = Vec::new();
+    for interface in try!(get_if_addrs::get_if_addrs()) {
+        let ip_address = interface.ip();
+        if is_usable(&ip_address) && !ip_addresses.contains(&ip_address) {
+            ip_addresses.push(ip_address);
+        }
+    }
+
+    Ok(ip_addresses)
+}
+
+/// Selects the address stamped on measurements. A configured address always wins, followed by
+/// the address the bridge observed (the public address behind nat) and finally the first local
+/// interface address, preferring ipv4.
+pub fn select(configured: &str, observed: Option<IpAddr>, local_ip_addresses: &Vec<IpAddr>) -> String {
+    if !configured.is_empty() {
+        return configured.to_owned();
+    }
+
+    if let Some(observed) = observed {
+        if is_usable(&observed) {
+            return observed.to_string();
+        }
+    }
+
+    local_ip_addresses.iter().find(|x| x.is_ipv4())
+        .or(local_ip_addresses.iter().nth(0))
+        .map(|x| x.to_string())
+        .unwrap_or(String::new())
+}
+
+fn is_usable(ip_address: &IpAddr) -> bool {
+    match *ip_address {
+        IpAddr::V4(ref ip_address) => !ip_address.is_loopback() && !ip_address.is_link_local() && !ip_address.is_unspecified(),
+        IpAddr::V6(ref ip_address) => !ip_address.is_loopback() && !ip_address.is_unspecified() && (ip_address.segments()[0] & 0xffc0) != 0xfe80,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    fn ip_addresses(values: &[&str]) -> Vec<IpAddr> {
+        values.iter().map(|x| IpAddr::from_str(x).unwrap()).collect()
+    }
+
+    #[test]
+    fn select_prefers_configured_then_observed_then_local() {
+        let local_ip_addresses = ip_addresses(&["2001:db8::2", "192.168.1.2", "10.0.0.2"]);
+        let observed = Some(IpAddr::from_str("198.51.100.7").unwrap());
+        let loopback = Some(IpAddr::from_str("127.0.0.1").unwrap());
+        let cases = [
+            ("203.0.113.1", observed, &local_ip_addresses, "203.0.113.1"),
+            ("", observed, &local_ip_addresses, "198.51.100.7"),
+            ("", loopback, &local_ip_addresses, "192.168.1.2"),
+            ("", None, &local_ip_addresses, "192.168.1.2"),
+        ];
+
+        for &(configured, observed, local_ip_addresses, expected) in cases.iter() {
+            assert_eq!(select(configured, observed, local_ip_addresses), expected, "{} {:?} {:?}", configured, observed, local_ip_addresses);
+        }
+
+        assert_eq!(select("", None, &ip_addresses(&["2001:db8::2"])), "2001:db8::2");
+        assert_eq!(select("", None, &Vec::new()), "");
+    }
+
+    #[test]
+    fn is_usable_rejects_loopback_link_local_and_unspecified() {
+        let cases = [
+            ("192.168.1.2", true),
+            ("198.51.100.7", true),
+            ("2001:db8::2", true),
+            ("127.0.0.1", false),
+            ("127.1.2.3", false),
+            ("::1", false),
+            ("169.254.10.1", false),
+            ("fe80::1", false),
+            ("febf::1", false),
+            ("0.0.0.0", false),
+            ("::", false),
+        ];
+
+        for &(ip_address, usable) in cases.iter() {
+            assert_eq!(is_usable(&IpAddr::from_str(ip_address).unwrap()), usable, "{}", ip_address);
+        }
+    }
+}
@@ -2,167 +2,495 @@
 extern crate bson;
 #[macro_use]
 extern crate chan;
+extern crate chan_signal;
 #[macro_use]
 extern crate clap;
 extern crate curl;
+extern crate get_if_addrs;
 extern crate proddle;
 extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 #[macro_use]
 extern crate slog;
 #[macro_use]
 extern crate slog_scope;
 extern crate slog_term;
 extern crate time;
+extern crate toml;
 
 use bson::Document;
-use clap::{App, ArgMatches};
-use proddle::ProddleError;
+use chan_signal::Signal;
+use clap::App;
+use proddle::{self, BucketRing, ProddleError, VantageConfig};
 use slog::{DrainExt, Logger};
 
 mod client;
+mod config;
 mod executor;
+mod ip_address;
 mod measurement;
+mod metrics;
 mod operation_job;
+mod rate_limiter;
+mod reporter;
+mod retry_policy;
+mod spool;
+mod vantage_id;
 
 use client::Client;
+use config::Config;
 use executor::Executor;
+use metrics::Metrics;
 use operation_job::OperationJob;
+use reporter::{ReportSettings, Reporter};
 
 use std::collections::{BinaryHeap, HashMap};
-use std::net::SocketAddr;
-use std::str::FromStr;
-use std::sync::{Arc, RwLock};
-
-fn parse_args<'a>(matches: &'a ArgMatches) -> Result<(String, String, u64, usize, SocketAddr, u32, i32, u32, HashMap<&'a str, i64>, Vec<&'a str>), ProddleError> {
-    let hostname = try!(value_t!(matches, "HOSTNAME", String));
-    let ip_address = try!(value_t!(matches, "IP_ADDRESS", String));
-    let bucket_count = try!(value_t!(matches.value_of("BUCKET_COUNT"), u64));
-    let thread_count = try!(value_t!(matches.value_of("THREAD_COUNT"), usize));
-    let bridge_ip_address = try!(matches.value_of("BRIDGE_IP_ADDRESS").ok_or("failed to parse bridge ip address"));
-    let bridge_port = try!(value_t!(matches.value_of("BRIDGE_PORT"), u16));
-    let bridge_address = try!(SocketAddr::from_str(&format!("{}:{}", bridge_ip_address, bridge_port)));
-    let bridge_update_interval_seconds = try!(value_t!(matches.value_of("BRIDGE_UPDATE_INTERVAL_SECONDS"), u32));
-    let max_retries = try!(value_t!(matches.value_of("MAX_RETRIES"), i32));
-    let send_measurements_interval_seconds = try!(value_t!(matches.value_of("SEND_MEASUREMENTS_INTERVAL_SECONDS"), u32));
-    let include_tags = match matches.values_of("INCLUDE_TAGS") {
-        Some(include_tags) => {
-            let mut hash_map = HashMap::new();
-            for include_tag in include_tags {
-                let mut split_values = include_tag.split("|");
-                let tag = try!(split_values.nth(0).ok_or("failed to fetch include tag"));
-                let interval = try!(try!(split_values.nth(0).ok_or("failed to fetch include tag interval")).parse::<i64>());
-
-                hash_map.insert(tag, interval);
-            }
+use std::sync::{Arc, Mutex, RwLock};
 
-            hash_map
-        },
-        None => HashMap::new(),
-    };
-
-    let exclude_tags = match matches.values_of("EXCLUDE_TAGS") {
-        Some(exclude_tags) => exclude_tags.collect(),
-        None => Vec::new(),
-    };
-
-    Ok((hostname, ip_address, bucket_count, thread_count, bridge_address, bridge_update_interval_seconds, 
-        max_retries, send_measurements_interval_seconds, include_tags, exclude_tags))
-}
+static EXECUTE_OPERATIONS_INTERVAL_SECONDS: i64 = 5;
 
 pub fn main() {
     slog_scope::set_global_logger(Logger::root(slog_term::streamer().build().fuse(), o![]));
+
+    //register for signals before any threads are started so every thread blocks them
+    let signal_rx = chan_signal::notify(&[Signal::HUP, Signal::INT, Signal::TERM]);
+
     let yaml = load_yaml!("args.yaml");
     let matches = App::from_yaml(yaml).get_matches();
     
     //initialize vantage parameters
-    info!("parsing command line arguments");
-    let (hostname, ip_address, bucket_count, thread_count, socket_addr, bridge_update_interval_seconds, 
-            max_retries, send_measurements_interval_seconds, include_tags, exclude_tags) = match parse_args(&matches) {
-        Ok(args) => args,
+    let config_file = matches.value_of("CONFIG_FILE").map(|x| x.to_owned());
+    let config_result = match config_file {
+        Some(ref config_file) => {
+            info!("parsing configuration file '{}'", config_file);
+            Config::from_file(config_file)
+        },
+        None => {
+            info!("parsing command line arguments");
+            Config::from_args(&matches)
+        },
+    };
+
+    //the local configuration is overlaid by configuration pushed from the bridge
+    let mut local_config = match config_result {
+        Ok(config) => config,
         Err(e) => panic!("{}", e),
     };
+    let mut vantage_config = VantageConfig::default();
+    let mut config = local_config.clone();
 
     //initialize vantage data structures
     info!("initializing vantage data structures");
     let mut operations: HashMap<u64, BinaryHeap<OperationJob>> = HashMap::new();
     let mut operation_bucket_hashes: HashMap<u64, u64> = HashMap::new();
-    let client = Arc::new(RwLock::new(Client::new(socket_addr.clone())));
+    let mut operations_version: Option<i64> = None;
+    let vantage_id = match vantage_id::load_or_create(&config.id_file) {
+        Ok(vantage_id) => vantage_id,
+        Err(e) => panic!("failed to load vantage id: {}", e),
+    };
+
+    info!("starting vantage '{}' with id '{}'", config.hostname, vantage_id);
+    let metrics = Arc::new(Mutex::new(Metrics::new(&vantage_id, &config.hostname)));
+    if let Some(ref metrics_address) = config.metrics_address {
+        info!("serving metrics on '{}'", metrics_address);
+        if let Err(e) = proddle::serve_metrics(metrics_address, metrics.clone()) {
+            panic!("failed to start metrics endpoint: {}", e);
+        }
+    }
+
+    let client = match config.bridge_address() {
+        Ok(socket_addr) => Arc::new(RwLock::new(Client::new(socket_addr, &vantage_id))),
+        Err(e) => panic!("{}", e),
+    };
 
     //populate operations with buckets
-    let mut counter = 0;
-    let delta = u64::max_value() / bucket_count;
-    for _ in 0..bucket_count {
-        operations.insert(counter, BinaryHeap::new());
-        operation_bucket_hashes.insert(counter, 0);
-        counter += delta;
+    let bucket_ring = match BucketRing::new(config.bucket_count) {
+        Ok(bucket_ring) => bucket_ring,
+        Err(e) => panic!("{}", e),
+    };
+
+    for bucket_key in bucket_ring.keys() {
+        operations.insert(*bucket_key, BinaryHeap::new());
+        operation_bucket_hashes.insert(*bucket_key, 0);
     }
 
     //initialize operations
-    {
-        let mut client = client.write().unwrap();
-        match client.update_operations(&mut operations, &mut operation_bucket_hashes, &include_tags, &exclude_tags) {
-            Ok(updated_operations_count) => {
-                if updated_operations_count > 0 {
-                    info!("updated {} operation(s)", updated_operations_count);
-                }
-            },
-            Err(e) => error!("{}", e),
-        }
-    }
+    update_operations(&client, &mut operations, &mut operation_bucket_hashes, &mut operations_version, &local_config, &mut vantage_config, &mut config, &metrics);
 
-    //start recv measurement channel
-    let (measurement_tx, measurement_rx) = chan::sync(50);
-    let t_client = client.clone();
-    std::thread::spawn(move || {
-        let mut measurement_buffer: Vec<Document> = Vec::new();
-        let tick = chan::tick_ms(send_measurements_interval_seconds * 1000);
+    //start recv measurement channel, unbounded so drivers never wait on a measurement upload
+    let (measurement_tx, measurement_rx) = chan::async();
+    let (send_measurements_interval_tx, send_measurements_interval_rx) = chan::async();
+    let (t_client, t_metrics) = (client.clone(), metrics.clone());
+    let send_measurements_interval_seconds = config.send_measurements_interval_seconds;
+    let spool_file = config.spool_file.clone();
 
+    //measurements spooled on a previous shutdown are resent first
+    let mut measurement_buffer: Vec<Document> = match spool_file {
+        Some(ref spool_file) => match spool::read(spool_file) {
+            Ok(measurements) => measurements,
+            Err(e) => panic!("failed to read spool file '{}': {}", spool_file, e),
+        },
+        None => Vec::new(),
+    };
+
+    let mut spooled = measurement_buffer.len() > 0;
+    if spooled {
+        info!("loaded {} spooled measurement(s)", measurement_buffer.len());
+    }
+
+    let measurement_handle = std::thread::spawn(move || {
+        let mut tick = chan::tick_ms(send_measurements_interval_seconds * 1000);
         loop {
+            let mut updated_interval_seconds = None;
+            let mut closed = false;
             chan_select! {
                 measurement_rx.recv() -> measurement => {
                     match measurement {
                         Some(measurement) => measurement_buffer.push(measurement),
-                        None => error!("failed to retrieve measurement from channel"),
+                        None => closed = true,
                     }
                 },
                 tick.recv() => {
                     if measurement_buffer.len() > 0 {
                         info!("sending {} measurements to bridge", measurement_buffer.len());
                         let mut client = t_client.write().unwrap();
-                        if let Err(e) = client.send_measurements(&mut measurement_buffer) {
-                            error!("failed to send measurements: {}", e);
-                        };
+                        match client.send_measurements(&mut measurement_buffer) {
+                            Ok(failure_count) => {
+                                if failure_count > 0 {
+                                    warn!("bridge failed to store {} measurement(s), keeping them to resend", failure_count);
+                                }
+
+                                t_metrics.lock().unwrap().last_measurement_send = Some(time::now_utc().to_timespec().sec);
+                            },
+                            Err(e) => error!("failed to send measurements: {}", e),
+                        }
                     }
                 },
+                send_measurements_interval_rx.recv() -> interval_seconds => {
+                    updated_interval_seconds = interval_seconds;
+                },
+            }
+
+            t_metrics.lock().unwrap().measurement_buffer_depth = measurement_buffer.len();
+
+            //the spool file is removed once its measurements have been delivered
+            if let (true, true, Some(spool_file)) = (spooled, measurement_buffer.is_empty(), spool_file.as_ref()) {
+                match spool::remove(spool_file) {
+                    Ok(_) => spooled = false,
+                    Err(e) => error!("failed to remove spool file '{}': {}", spool_file, e),
+                }
+            }
+
+            //the channel closes once the executor has shut down and every measurement is buffered
+            if closed {
+                return flush_measurements(&t_client, &mut measurement_buffer, &spool_file);
+            }
+
+            //chan_select! borrows the tick receiver so it may only be replaced afterwards
+            if let Some(interval_seconds) = updated_interval_seconds {
+                tick = chan::tick_ms(interval_seconds * 1000);
             }
         }
     });
 
     //start operation loop
-    let mut executor = Executor::new(thread_count, &hostname, &ip_address, max_retries, measurement_tx);
+    let mut executor = match Executor::new(&config, &vantage_id, measurement_tx) {
+        Ok(executor) => executor,
+        Err(e) => panic!("failed to initialize executor: {}", e),
+    };
 
-    let execute_operations_tick = chan::tick_ms(5 * 1000);
-    let bridge_update_tick = chan::tick_ms(bridge_update_interval_seconds * 1000);
-    loop {
+    let mut vantage_ip_address = String::new();
+    refresh_ip_address(&client, &config, &mut executor, &mut vantage_ip_address, &metrics);
+
+    //statistics are gathered every execution and sent with heartbeats from the reporting thread
+    let mut reporter = Reporter::start(client.clone(), metrics.clone(), ReportSettings::new(&config, vantage_config.timestamp));
+
+    let execute_operations_tick = chan::tick_ms(EXECUTE_OPERATIONS_INTERVAL_SECONDS as u32 * 1000);
+    let mut bridge_update_tick = chan::tick_ms(config.bridge_update_interval_seconds * 1000);
+    let mut shutdown = false;
+    while !shutdown {
+        let mut reload = false;
         chan_select! {
             execute_operations_tick.recv() => {
                 if let Err(e) = execute_operations(&mut operations, &mut executor) {
                     error!("{}", e);
                 }
+
+                let statistics = executor.take_statistics();
+                {
+                    let mut metrics = metrics.lock().unwrap();
+                    metrics.statistics.merge(&statistics);
+                    metrics.executor_status = executor.status();
+                    metrics.scheduled_operations = operations.iter().map(|(bucket_key, operation_jobs)| (*bucket_key, operation_jobs.len())).collect();
+                }
+
+                reporter.add_statistics(statistics);
             },
             bridge_update_tick.recv() => {
-                let mut client = client.write().unwrap();
-                match client.update_operations(&mut operations, &mut operation_bucket_hashes, &include_tags, &exclude_tags) {
-                    Ok(updated_operations_count) => {
-                        if updated_operations_count > 0 {
-                            info!("updated {} operation(s)", updated_operations_count);
-                        }
+                update_operations(&client, &mut operations, &mut operation_bucket_hashes, &mut operations_version, &local_config, &mut vantage_config, &mut config, &metrics);
+                refresh_ip_address(&client, &config, &mut executor, &mut vantage_ip_address, &metrics);
+                reporter.update_settings(ReportSettings::new(&config, vantage_config.timestamp));
+            },
+            signal_rx.recv() -> signal => {
+                match signal {
+                    Some(Signal::HUP) => reload = true,
+                    Some(signal) => {
+                        info!("received {:?}, shutting down", signal);
+                        shutdown = true;
                     },
-                    Err(e) => error!("{}", e),
+                    None => error!("failed to recv signal"),
                 }
+            },
+        }
+
+        if !reload {
+            continue;
+        }
+
+        //reload configuration, keeping the current one if the new one is invalid
+        let mut reloaded_local_config = match config_file {
+            Some(ref config_file) => match Config::from_file(config_file) {
+                Ok(reloaded_local_config) => reloaded_local_config,
+                Err(e) => {
+                    error!("failed to reload configuration: {}", e);
+                    continue;
+                },
+            },
+            None => {
+                warn!("ignoring reload request, vantage was not started with a configuration file");
+                continue;
+            },
+        };
+
+        if reloaded_local_config == local_config {
+            info!("configuration unchanged");
+            continue;
+        }
+
+        if reloaded_local_config.metrics_address != local_config.metrics_address || reloaded_local_config.spool_file != local_config.spool_file
+                || reloaded_local_config.id_file != local_config.id_file {
+            warn!("metrics_address, spool_file and id_file changes take effect on restart");
+            reloaded_local_config.metrics_address = local_config.metrics_address.clone();
+            reloaded_local_config.spool_file = local_config.spool_file.clone();
+            reloaded_local_config.id_file = local_config.id_file.clone();
+        }
+
+        let reloaded_config = reloaded_local_config.with_vantage_config(&vantage_config);
+        if let Err(e) = reloaded_config.validate() {
+            error!("failed to apply bridge configuration version {} to reloaded configuration: {}", vantage_config.timestamp, e);
+            continue;
+        }
+
+        if let Err(e) = executor.reconfigure(&reloaded_config) {
+            error!("failed to reconfigure executor: {}", e);
+            continue;
+        }
+
+        if let Ok(socket_addr) = reloaded_config.bridge_address() {
+            client.write().unwrap().set_socket_addr(socket_addr);
+        }
+
+        if reloaded_config.bridge_update_interval_seconds != config.bridge_update_interval_seconds {
+            bridge_update_tick = chan::tick_ms(reloaded_config.bridge_update_interval_seconds * 1000);
+        }
+
+        if reloaded_config.send_measurements_interval_seconds != config.send_measurements_interval_seconds {
+            send_measurements_interval_tx.send(reloaded_config.send_measurements_interval_seconds);
+        }
+
+        let resync = operation_filters_changed(&config, &reloaded_config) || reloaded_config.groups != config.groups;
+        let rebucket = reloaded_config.bucket_count != config.bucket_count;
+        local_config = reloaded_local_config;
+        config = reloaded_config;
+        refresh_ip_address(&client, &config, &mut executor, &mut vantage_ip_address, &metrics);
+        info!("reloaded configuration");
+
+        if rebucket {
+            match BucketRing::new(config.bucket_count) {
+                Ok(bucket_ring) => {
+                    info!("moving operations into {} bucket(s)", bucket_ring.bucket_count());
+                    rebucket_operations(&mut operations, &mut operation_bucket_hashes, &bucket_ring);
+                },
+                Err(e) => error!("failed to change bucket_count: {}", e),
             }
         }
+
+        if resync {
+            for (_, operation_bucket_hash) in operation_bucket_hashes.iter_mut() {
+                *operation_bucket_hash = 0;
+            }
+            operations_version = None;
+        }
+
+        if resync || rebucket {
+            update_operations(&client, &mut operations, &mut operation_bucket_hashes, &mut operations_version, &local_config, &mut vantage_config, &mut config, &metrics);
+        }
+
+        reporter.update_settings(ReportSettings::new(&config, vantage_config.timestamp));
+    }
+
+    //wait for in flight measurements, the measurement thread then flushes them to the bridge
+    info!("waiting for in flight measurements to complete");
+    reporter.add_statistics(executor.shutdown());
+    reporter.shutdown();
+
+    let exit_code = match measurement_handle.join() {
+        Ok(true) => {
+            info!("shutdown complete");
+            0
+        },
+        Ok(false) => 1,
+        Err(_) => {
+            error!("measurement thread panicked during shutdown");
+            1
+        },
+    };
+
+    std::process::exit(exit_code);
+}
+
+//send buffered measurements to the bridge, persisting those which are not stored to the spool
+//file, returns false if measurements were lost
+fn flush_measurements(client: &Arc<RwLock<Client>>, measurement_buffer: &mut Vec<Document>, spool_file: &Option<String>) -> bool {
+    if measurement_buffer.is_empty() {
+        return true;
+    }
+
+    info!("sending {} measurements to bridge", measurement_buffer.len());
+    if let Err(e) = client.write().unwrap().send_measurements(measurement_buffer) {
+        error!("failed to send measurements: {}", e);
+    }
+
+    match (measurement_buffer.is_empty(), spool_file.as_ref()) {
+        (true, Some(spool_file)) => {
+            if let Err(e) = spool::remove(spool_file) {
+                error!("failed to remove spool file '{}': {}", spool_file, e);
+            }
+
+            true
+        },
+        (true, None) => true,
+        (false, Some(spool_file)) => {
+            match spool::write(spool_file, measurement_buffer) {
+                Ok(_) => {
+                    info!("spooled {} measurement(s) to '{}'", measurement_buffer.len(), spool_file);
+                    true
+                },
+                Err(e) => {
+                    error!("failed to spool {} measurement(s) to '{}': {}", measurement_buffer.len(), spool_file, e);
+                    false
+                },
+            }
+        },
+        (false, None) => {
+            error!("{} measurement(s) lost without a spool file", measurement_buffer.len());
+            false
+        },
+    }
+}
+
+fn update_operations(client: &Arc<RwLock<Client>>, operations: &mut HashMap<u64, BinaryHeap<OperationJob>>,
+                     operation_bucket_hashes: &mut HashMap<u64, u64>, operations_version: &mut Option<i64>, local_config: &Config,
+                     vantage_config: &mut VantageConfig, config: &mut Config, metrics: &Arc<Mutex<Metrics>>) {
+    let mut client = client.write().unwrap();
+    loop {
+        let pushed_vantage_config = match client.update_operations(operations, operation_bucket_hashes, operations_version, config, vantage_config.timestamp) {
+            Ok((updated_operations_count, pushed_vantage_config)) => {
+                metrics.lock().unwrap().last_bridge_sync = Some(time::now_utc().to_timespec().sec);
+                if updated_operations_count > 0 {
+                    info!("updated {} operation(s)", updated_operations_count);
+                }
+
+                match pushed_vantage_config {
+                    Some(pushed_vantage_config) => pushed_vantage_config,
+                    None => return,
+                }
+            },
+            Err(e) => {
+                error!("{}", e);
+                return;
+            },
+        };
+
+        //apply the bridge configuration, an invalid one is rejected and offered again on the next update
+        let pushed_config = local_config.with_vantage_config(&pushed_vantage_config);
+        if let Err(e) = pushed_config.validate() {
+            error!("rejected bridge configuration version {}: {}", pushed_vantage_config.timestamp, e);
+            return;
+        }
+
+        info!("applied bridge configuration version {}", pushed_vantage_config.timestamp);
+        metrics.lock().unwrap().config_version = pushed_vantage_config.timestamp;
+        let resync = operation_filters_changed(config, &pushed_config);
+        *vantage_config = pushed_vantage_config;
+        *config = pushed_config;
+        if !resync {
+            return;
+        }
+
+        //operations were filtered with the previous configuration so every bucket is refetched
+        for (_, operation_bucket_hash) in operation_bucket_hashes.iter_mut() {
+            *operation_bucket_hash = 0;
+        }
+        *operations_version = None;
+    }
+}
+
+//move scheduled jobs into the buckets of a new ring, keeping their schedules. bucket hashes are
+//computed from the operations held so only buckets which differ from the bridge are refetched
+fn rebucket_operations(operations: &mut HashMap<u64, BinaryHeap<OperationJob>>, operation_bucket_hashes: &mut HashMap<u64, u64>,
+                       bucket_ring: &BucketRing) {
+    let mut rebucketed_operations: HashMap<u64, BinaryHeap<OperationJob>> = HashMap::new();
+    for bucket_key in bucket_ring.keys() {
+        rebucketed_operations.insert(*bucket_key, BinaryHeap::new());
+    }
+
+    for (_, binary_heap) in operations.drain() {
+        for operation_job in binary_heap.into_vec() {
+            let bucket_key = bucket_ring.domain_bucket_key(&operation_job.operation.domain);
+            rebucketed_operations.entry(bucket_key).or_insert(BinaryHeap::new()).push(operation_job);
+        }
+    }
+
+    operation_bucket_hashes.clear();
+    for (bucket_key, binary_heap) in rebucketed_operations.iter() {
+        operation_bucket_hashes.insert(*bucket_key, proddle::bucket_digest(binary_heap.iter().map(|x| &x.operation)));
     }
+
+    *operations = rebucketed_operations;
+}
+
+//select the vantage address from the configuration, the bridge and local interfaces, it is
+//refreshed with every bridge update so nat and dhcp changes are reflected in measurements
+fn refresh_ip_address(client: &Arc<RwLock<Client>>, config: &Config, executor: &mut Executor, vantage_ip_address: &mut String,
+                      metrics: &Arc<Mutex<Metrics>>) {
+    let local_ip_addresses = match ip_address::local_ip_addresses() {
+        Ok(local_ip_addresses) => local_ip_addresses,
+        Err(e) => {
+            error!("failed to enumerate local interface addresses: {}", e);
+            Vec::new()
+        },
+    };
+
+    let observed_ip_address = client.read().unwrap().observed_ip_address();
+    let ip_address = ip_address::select(&config.ip_address, observed_ip_address, &local_ip_addresses);
+    if ip_address != *vantage_ip_address {
+        info!("vantage ip address changed from '{}' to '{}'", vantage_ip_address, ip_address);
+        *vantage_ip_address = ip_address.to_owned();
+        metrics.lock().unwrap().ip_address = ip_address.to_owned();
+    }
+
+    executor.set_ip_addresses(ip_address, local_ip_addresses.iter().map(|x| x.to_string()).collect());
+}
+
+//changed tags require every bucket to be refetched, unchanged operations keep their schedule
+fn operation_filters_changed(config: &Config, updated_config: &Config) -> bool {
+    updated_config.include_tags != config.include_tags || updated_config.exclude_tags != config.exclude_tags
+        || updated_config.max_jitter_seconds != config.max_jitter_seconds || updated_config.hostname != config.hostname
 }
 
 fn execute_operations(operations: &mut HashMap<u64, BinaryHeap<OperationJob>>, executor: &mut Executor) -> Result<(), ProddleError> {
@@ -186,12 +514,22 @@ fn execute_operations(operations: &mut HashMap<u64, BinaryHeap<OperationJob>>, e
                     },
                 };
 
-                let pool_operation_job = operation_job.clone();
-                operation_job.execution_time += operation_job.interval;
-                operation_jobs.push(operation_job);
+                //add job to executor, a delayed job is retried next tick without advancing its schedule
+                if let Some(mut delayed_operation_job) = try!(executor.execute_operation(operation_job.clone())) {
+                    delayed_operation_job.execution_time = now + EXECUTE_OPERATIONS_INTERVAL_SECONDS;
+                    operation_jobs.push(delayed_operation_job);
+                    continue;
+                }
+
+                //reschedule unless the operation has no further executions, skipping passed executions
+                while operation_job.reschedule() {
+                    if operation_job.execution_time >= now {
+                        operation_jobs.push(operation_job);
+                        break;
+                    }
 
-                //add job to executor
-                let _ = executor.execute_operation(pool_operation_job);
+                    executor.record_missed();
+                }
             } else {
                 break;
             }
@@ -200,3 +538,67 @@ fn execute_operations(operations: &mut HashMap<u64, BinaryHeap<OperationJob>>, e
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proddle::{Operation, Parameter};
+
+    fn operation_job(domain: &str, execution_time: i64) -> OperationJob {
+        let operation = Operation {
+            version: 1,
+            deleted: false,
+            timestamp: 1500000000,
+            measurement_class: String::from("HttpGet"),
+            domain: domain.to_owned(),
+            parameters: vec![Parameter { name: String::from("timeout"), value: String::from("30") }],
+            tags: vec![String::from("top-sites")],
+            schedule: None,
+            start_timestamp: None,
+            end_timestamp: None,
+        };
+
+        let mut operation_job = OperationJob::new(operation, 300, "vantage", 0).unwrap().unwrap();
+        operation_job.execution_time = execution_time;
+        operation_job
+    }
+
+    //domains and execution times of the jobs in each bucket
+    fn scheduled(operations: &HashMap<u64, BinaryHeap<OperationJob>>) -> HashMap<u64, Vec<(String, i64)>> {
+        operations.iter().map(|(bucket_key, binary_heap)| {
+            let mut jobs: Vec<(String, i64)> = binary_heap.iter().map(|x| (x.operation.domain.to_owned(), x.execution_time)).collect();
+            jobs.sort();
+            (*bucket_key, jobs)
+        }).collect()
+    }
+
+    #[test]
+    fn rebucketing_keeps_jobs_and_their_schedules() {
+        let domains = ["google.com", "github.com", "amazon.com", "example.com", "wikipedia.org"];
+        let mut operations: HashMap<u64, BinaryHeap<OperationJob>> = HashMap::new();
+        operations.insert(0, domains.iter().enumerate().map(|(i, x)| operation_job(x, 1000 + i as i64)).collect());
+        let mut operation_bucket_hashes = HashMap::new();
+
+        for bucket_count in [4, 7, 1].iter() {
+            let bucket_ring = BucketRing::new(*bucket_count).unwrap();
+            rebucket_operations(&mut operations, &mut operation_bucket_hashes, &bucket_ring);
+
+            //every bucket of the ring is present, holding the jobs of its domains
+            let mut bucket_keys: Vec<u64> = operations.keys().cloned().collect();
+            bucket_keys.sort();
+            assert_eq!(bucket_keys, bucket_ring.keys().to_vec());
+            let scheduled = scheduled(&operations);
+            for (i, domain) in domains.iter().enumerate() {
+                assert!(scheduled[&bucket_ring.domain_bucket_key(domain)].contains(&(domain.to_string(), 1000 + i as i64)));
+            }
+
+            assert_eq!(scheduled.values().map(|x| x.len()).sum::<usize>(), domains.len());
+
+            //hashes are recomputed from the jobs held
+            assert_eq!(operation_bucket_hashes.len(), bucket_ring.keys().len());
+            for (bucket_key, binary_heap) in operations.iter() {
+                assert_eq!(operation_bucket_hashes[bucket_key], proddle::bucket_digest(binary_heap.iter().map(|x| &x.operation)));
+            }
+        }
+    }
+}
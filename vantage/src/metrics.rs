@@ -0,0 +1,160 @@
+use proddle::{MetricsSource, write_metric};
+use time;
+
+use executor::{ExecutorStatistics, ExecutorStatus};
+
+use std::collections::HashMap;
+
+/// Vantage state exposed by the metrics endpoint. Statistics are cumulative since startup.
+pub struct Metrics {
+    pub vantage_id: String,
+    pub hostname: String,
+    pub ip_address: String,
+    pub config_version: i64,
+    pub start_timestamp: i64,
+    pub scheduled_operations: HashMap<u64, usize>,
+    pub statistics: ExecutorStatistics,
+    pub executor_status: ExecutorStatus,
+    pub measurement_buffer_depth: usize,
+    pub last_bridge_sync: Option<i64>,
+    pub last_measurement_send: Option<i64>,
+    pub last_heartbeat: Option<i64>,
+    pub clock_offset_milliseconds: Option<i64>,
+}
+
+impl Metrics {
+    pub fn new(vantage_id: &str, hostname: &str) -> Metrics {
+        Metrics {
+            vantage_id: vantage_id.to_owned(),
+            hostname: hostname.to_owned(),
+            ip_address: String::new(),
+            config_version: 0,
+            start_timestamp: time::now_utc().to_timespec().sec,
+            scheduled_operations: HashMap::new(),
+            statistics: ExecutorStatistics::default(),
+            executor_status: ExecutorStatus::default(),
+            measurement_buffer_depth: 0,
+            last_bridge_sync: None,
+            last_measurement_send: None,
+            last_heartbeat: None,
+            clock_offset_milliseconds: None,
+        }
+    }
+
+    fn saturation(&self) -> f64 {
+        match self.executor_status.in_flight_capacity {
+            0 => 0.0,
+            capacity => self.executor_status.in_flight as f64 / capacity as f64,
+        }
+    }
+}
+
+impl MetricsSource for Metrics {
+    /// Renders metrics in the prometheus text exposition format.
+    fn to_prometheus(&self) -> String {
+        let mut output = String::new();
+        let labels = format!("vantage_id=\"{}\",hostname=\"{}\"", self.vantage_id, self.hostname);
+
+        let mut buckets: Vec<&u64> = self.scheduled_operations.keys().collect();
+        buckets.sort();
+        let scheduled_operations: Vec<(String, f64)> = buckets.iter()
+            .map(|x| (format!("{},bucket=\"{}\"", labels, x), self.scheduled_operations[*x] as f64)).collect();
+        write_metric(&mut output, "proddle_vantage_scheduled_operations", "Operations scheduled in each bucket.", "gauge", &scheduled_operations);
+
+        let statistics = &self.statistics;
+        for &(name, help, value) in [
+                ("proddle_vantage_executions_total", "Operation executions dispatched to a driver.", statistics.executed),
+                ("proddle_vantage_dropped_executions_total", "Queued executions dropped by the overflow policy.", statistics.dropped),
+                ("proddle_vantage_skipped_executions_total", "Executions skipped by the overflow policy.", statistics.skipped),
+                ("proddle_vantage_delayed_executions_total", "Executions delayed by a full work queue.", statistics.delayed),
+                ("proddle_vantage_missed_executions_total", "Scheduled executions that never ran.", statistics.missed),
+                ("proddle_vantage_late_executions_total", "Executions started past their scheduled time.", statistics.late),
+                ("proddle_vantage_retries_total", "Failed measurement attempts scheduled for retry.", statistics.retried),
+            ].iter() {
+            write_metric(&mut output, name, help, "counter", &[(labels.to_owned(), value as f64)]);
+        }
+
+        let mut results = vec![(format!("{},result=\"success\"", labels), statistics.succeeded as f64)];
+        let mut error_categories: Vec<&String> = statistics.errors.keys().collect();
+        error_categories.sort();
+        for error_category in error_categories {
+            results.push((format!("{},result=\"{}\"", labels, error_category), statistics.errors[error_category] as f64));
+        }
+        write_metric(&mut output, "proddle_vantage_measurements_total", "Measurement attempts by result or error category.", "counter", &results);
+
+        let status = &self.executor_status;
+        for &(name, help, value) in [
+                ("proddle_vantage_queue_depth", "Executions waiting for a driver.", status.queue_depth as f64),
+                ("proddle_vantage_queue_capacity", "Capacity of the executor work queue.", status.queue_capacity as f64),
+                ("proddle_vantage_in_flight", "Transfers in progress.", status.in_flight as f64),
+                ("proddle_vantage_in_flight_capacity", "Maximum transfers in progress across drivers.", status.in_flight_capacity as f64),
+                ("proddle_vantage_executor_saturation", "Ratio of transfers in progress to capacity.", self.saturation()),
+                ("proddle_vantage_measurement_buffer_depth", "Measurements waiting to be sent to the bridge.", self.measurement_buffer_depth as f64),
+                ("proddle_vantage_start_timestamp_seconds", "Unix timestamp the vantage started.", self.start_timestamp as f64),
+            ].iter() {
+            write_metric(&mut output, name, help, "gauge", &[(labels.to_owned(), value)]);
+        }
+
+        if let Some(last_bridge_sync) = self.last_bridge_sync {
+            write_metric(&mut output, "proddle_vantage_last_bridge_sync_timestamp_seconds", "Unix timestamp of the last successful operation update.",
+                "gauge", &[(labels.to_owned(), last_bridge_sync as f64)]);
+        }
+
+        if let Some(last_measurement_send) = self.last_measurement_send {
+            write_metric(&mut output, "proddle_vantage_last_measurement_send_timestamp_seconds", "Unix timestamp measurements were last sent to the bridge.",
+                "gauge", &[(labels.to_owned(), last_measurement_send as f64)]);
+        }
+
+        if let Some(last_heartbeat) = self.last_heartbeat {
+            write_metric(&mut output, "proddle_vantage_last_heartbeat_timestamp_seconds", "Unix timestamp of the last heartbeat acknowledged by the bridge.",
+                "gauge", &[(labels.to_owned(), last_heartbeat as f64)]);
+        }
+
+        if let Some(clock_offset_milliseconds) = self.clock_offset_milliseconds {
+            write_metric(&mut output, "proddle_vantage_clock_offset_seconds", "Bridge clock minus vantage clock estimated by the latest exchange with the bridge.",
+                "gauge", &[(labels.to_owned(), clock_offset_milliseconds as f64 / 1000.0)]);
+        }
+
+        output
+    }
+
+    fn to_json(&self) -> String {
+        let now = time::now_utc().to_timespec().sec;
+        let statistics = &self.statistics;
+        let status = json!({
+            "vantage_id": self.vantage_id,
+            "hostname": self.hostname,
+            "ip_address": self.ip_address,
+            "config_version": self.config_version,
+            "start_timestamp": self.start_timestamp,
+            "uptime_seconds": now - self.start_timestamp,
+            "last_bridge_sync": self.last_bridge_sync,
+            "last_measurement_send": self.last_measurement_send,
+            "last_heartbeat": self.last_heartbeat,
+            "clock_offset_milliseconds": self.clock_offset_milliseconds,
+            "scheduled_operations": self.scheduled_operations.values().sum::<usize>(),
+            "bucket_count": self.scheduled_operations.len(),
+            "measurement_buffer_depth": self.measurement_buffer_depth,
+            "executor": {
+                "queue_depth": self.executor_status.queue_depth,
+                "queue_capacity": self.executor_status.queue_capacity,
+                "in_flight": self.executor_status.in_flight,
+                "in_flight_capacity": self.executor_status.in_flight_capacity,
+                "saturation": self.saturation(),
+            },
+            "statistics": {
+                "executed": statistics.executed,
+                "dropped": statistics.dropped,
+                "skipped": statistics.skipped,
+                "delayed": statistics.delayed,
+                "missed": statistics.missed,
+                "late": statistics.late,
+                "retried": statistics.retried,
+                "succeeded": statistics.succeeded,
+                "errors": statistics.errors,
+            },
+        });
+
+        status.to_string()
+    }
+}
@@ -1,25 +1,114 @@
 extern crate proddle;
 extern crate time;
 
-use proddle::Operation;
+use proddle::{Operation, ProddleError, Schedule, StableHasher};
+use rand::{self, Rng};
 
 use std::cmp::{Ordering, PartialOrd};
 
 #[derive(Clone)]
 pub struct OperationJob  {
     pub execution_time: i64,
+    pub scheduled_time: i64,
     pub operation: Operation,
     pub interval: i64,
+    pub schedule: Option<Schedule>,
+    pub max_jitter: i64,
 }
 
 impl OperationJob {
-    pub fn new(operation: Operation, interval: i64) -> OperationJob {
+    /// Creates a job for the operation, returning None if the operation window has already closed.
+    /// Interval based jobs are offset by a deterministic phase derived from the operation and
+    /// 'phase_key' so that jobs sharing an interval are spread evenly across it.
+    pub fn new(operation: Operation, interval: i64, phase_key: &str, max_jitter: i64) -> Result<Option<OperationJob>, ProddleError> {
         let now = time::now_utc().to_timespec().sec;
+        let schedule = match operation.schedule {
+            Some(ref schedule) => Some(try!(Schedule::parse(schedule))),
+            None => None,
+        };
 
-        OperationJob {
-            execution_time: (now - (now % interval) + interval),
+        //compute phase offset within interval, stable across restarts and rust releases
+        let mut hasher = StableHasher::new();
+        hasher.write_str(&operation.domain);
+        hasher.write_str(&operation.measurement_class);
+        hasher.write_str(phase_key);
+        let phase = (hasher.finish() % interval as u64) as i64;
+
+        //compute first scheduled time
+        let scheduled_time = match (&schedule, operation.start_timestamp) {
+            (&Some(Schedule::Once), start_timestamp) => {
+                //anchored to the operation so restarts and resyncs do not execute it again, it is
+                //still executed if the vantage learns of it within an interval of the anchor
+                let anchor = start_timestamp.unwrap_or(operation.timestamp);
+                if anchor + interval <= now {
+                    return Ok(None);
+                }
+
+                if anchor > now { anchor } else { now }
+            },
+            (&Some(ref schedule), Some(start_timestamp)) if start_timestamp > now => try!(schedule.next_after(start_timestamp - 1).ok_or("failed to compute schedule execution time")),
+            (&Some(ref schedule), _) => try!(schedule.next_after(now).ok_or("failed to compute schedule execution time")),
+            (&None, Some(start_timestamp)) if start_timestamp > now => start_timestamp + phase,
+            (&None, _) => {
+                let scheduled_time = now - (now % interval) + phase;
+                if scheduled_time <= now { scheduled_time + interval } else { scheduled_time }
+            },
+        };
+
+        let mut operation_job = OperationJob {
+            execution_time: scheduled_time,
+            scheduled_time: scheduled_time,
             operation: operation,
             interval: interval,
+            schedule: schedule,
+            max_jitter: max_jitter,
+        };
+
+        if operation_job.is_expired(scheduled_time) {
+            return Ok(None);
+        }
+
+        operation_job.execution_time = scheduled_time + operation_job.jitter();
+        Ok(Some(operation_job))
+    }
+
+    /// Advances the job to its next scheduled time, returning false once the operation has no
+    /// further executions within its window.
+    pub fn reschedule(&mut self) -> bool {
+        let scheduled_time = match self.schedule {
+            Some(ref schedule) => match schedule.next_after(self.scheduled_time) {
+                Some(scheduled_time) => scheduled_time,
+                None => return false,
+            },
+            None => self.scheduled_time + self.interval,
+        };
+
+        if self.is_expired(scheduled_time) {
+            return false;
+        }
+
+        self.scheduled_time = scheduled_time;
+        self.execution_time = scheduled_time + self.jitter();
+        true
+    }
+
+    //random delay bounded by the interval so jitter never reorders consecutive executions
+    fn jitter(&self) -> i64 {
+        let max_jitter = match self.schedule {
+            None if self.interval <= self.max_jitter => self.interval - 1,
+            _ => self.max_jitter,
+        };
+
+        match max_jitter > 0 {
+            true => rand::thread_rng().gen_range(0, max_jitter + 1),
+            false => 0,
+        }
+    }
+
+    fn is_expired(&self, execution_time: i64) -> bool {
+        match self.operation.end_timestamp {
+            Some(end_timestamp) => execution_time > end_timestamp,
+            None => false,
         }
     }
 }
@@ -43,3 +132,111 @@ impl PartialOrd for OperationJob {
         Some(self.cmp(other))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(domain: &str, schedule: Option<&str>, start_timestamp: Option<i64>, end_timestamp: Option<i64>) -> Operation {
+        Operation {
+            version: 1,
+            deleted: false,
+            timestamp: time::now_utc().to_timespec().sec,
+            measurement_class: String::from("HttpGet"),
+            domain: domain.to_owned(),
+            parameters: Vec::new(),
+            tags: vec![String::from("top-sites")],
+            schedule: schedule.map(|x| x.to_owned()),
+            start_timestamp: start_timestamp,
+            end_timestamp: end_timestamp,
+        }
+    }
+
+    fn now() -> i64 {
+        time::now_utc().to_timespec().sec
+    }
+
+    #[test]
+    fn phase_is_stable_and_within_interval() {
+        let interval = 300;
+        let mut phases = Vec::new();
+        for phase_key in ["vantage-a", "vantage-b", "vantage-c", "vantage-d"].iter() {
+            let before = now();
+            let operation_job = OperationJob::new(operation("google.com", None, None, None), interval, phase_key, 0).unwrap().unwrap();
+            assert!(operation_job.scheduled_time > before && operation_job.scheduled_time <= now() + interval);
+            assert_eq!(operation_job.execution_time, operation_job.scheduled_time);
+
+            let phase = operation_job.scheduled_time % interval;
+            assert!(phase >= 0 && phase < interval);
+            for _ in 0..10 {
+                let repeated_job = OperationJob::new(operation("google.com", None, None, None), interval, phase_key, 0).unwrap().unwrap();
+                assert_eq!(repeated_job.scheduled_time % interval, phase);
+            }
+
+            phases.push(phase);
+        }
+
+        //jobs of different vantages are spread across the interval
+        phases.sort();
+        phases.dedup();
+        assert!(phases.len() > 1, "{:?}", phases);
+    }
+
+    #[test]
+    fn jitter_is_bounded_by_max_jitter_and_interval() {
+        for &(interval, max_jitter, bound) in [(300, 10, 10), (300, 0, 0), (5, 100, 4)].iter() {
+            for _ in 0..100 {
+                let operation_job = OperationJob::new(operation("google.com", None, None, None), interval, "vantage", max_jitter).unwrap().unwrap();
+                let jitter = operation_job.execution_time - operation_job.scheduled_time;
+                assert!(jitter >= 0 && jitter <= bound, "interval {} max jitter {} jitter {}", interval, max_jitter, jitter);
+            }
+        }
+    }
+
+    #[test]
+    fn window_delays_or_prevents_the_first_run() {
+        let interval = 300;
+        let start_timestamp = now() + 86400;
+        let operation_job = OperationJob::new(operation("google.com", None, Some(start_timestamp), None), interval, "vantage", 0).unwrap().unwrap();
+        assert!(operation_job.scheduled_time >= start_timestamp && operation_job.scheduled_time < start_timestamp + interval);
+
+        //the first run falls after a window which has already closed
+        assert!(OperationJob::new(operation("google.com", None, None, Some(now() - 60)), interval, "vantage", 0).unwrap().is_none());
+        assert!(OperationJob::new(operation("google.com", None, Some(start_timestamp), Some(start_timestamp - 1)), interval, "vantage", 0).unwrap().is_none());
+        assert!(OperationJob::new(operation("google.com", None, None, Some(now() + 86400)), interval, "vantage", 0).unwrap().is_some());
+    }
+
+    #[test]
+    fn schedules_run_at_their_next_match() {
+        let hour_start = (now() / 3600 + 48) * 3600;
+        let operation_job = OperationJob::new(operation("google.com", Some("@hourly"), Some(hour_start), None), 300, "vantage", 0).unwrap().unwrap();
+        assert_eq!(operation_job.scheduled_time, hour_start);
+
+        let before = now();
+        let operation_job = OperationJob::new(operation("google.com", Some("@hourly"), None, None), 300, "vantage", 0).unwrap().unwrap();
+        assert_eq!(operation_job.scheduled_time % 3600, 0);
+        assert!(operation_job.scheduled_time > before && operation_job.scheduled_time <= now() + 3600);
+
+        assert!(OperationJob::new(operation("google.com", Some("@hourly"), None, Some(now() - 60)), 300, "vantage", 0).unwrap().is_none());
+        assert!(OperationJob::new(operation("google.com", Some("every hour"), None, None), 300, "vantage", 0).is_err());
+    }
+
+    #[test]
+    fn once_runs_at_its_anchor_within_an_interval() {
+        let interval = 300;
+        let anchor = now() + 3600;
+        let operation_job = OperationJob::new(operation("google.com", Some("@once"), Some(anchor), None), interval, "vantage", 0).unwrap().unwrap();
+        assert_eq!(operation_job.scheduled_time, anchor);
+
+        //an anchor in the recent past runs immediately, an older one never
+        let before = now();
+        let operation_job = OperationJob::new(operation("google.com", Some("@once"), Some(before - 60), None), interval, "vantage", 0).unwrap().unwrap();
+        assert!(operation_job.scheduled_time >= before && operation_job.scheduled_time <= now());
+        assert!(OperationJob::new(operation("google.com", Some("@once"), Some(now() - interval - 60), None), interval, "vantage", 0).unwrap().is_none());
+
+        //without a start timestamp the operation timestamp is the anchor
+        let mut anchored_operation = operation("google.com", Some("@once"), None, None);
+        anchored_operation.timestamp = now() - interval - 60;
+        assert!(OperationJob::new(anchored_operation, interval, "vantage", 0).unwrap().is_none());
+    }
+}
@@ -0,0 +1,333 @@
+use proddle::ProddleError;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+//seconds between sweeps removing idle per ip address and per domain buckets
+static EVICTION_INTERVAL_SECONDS: u64 = 60;
+//second level labels commonly used beneath country code top level domains
+static SECOND_LEVEL_LABELS: [&'static str; 7] = ["ac", "co", "com", "edu", "gov", "net", "org"];
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl RateLimit {
+    /// Parses a limit of the form 'rate|burst' where rate is in measurements per second.
+    pub fn parse(value: &str) -> Result<RateLimit, ProddleError> {
+        let mut split_values = value.split("|");
+        let rate = try!(try!(split_values.nth(0).ok_or("failed to parse rate limit rate")).parse::<f64>().map_err(|_| format!("invalid rate limit '{}'", value)));
+        let burst = match split_values.nth(0) {
+            Some(burst) => try!(burst.parse::<f64>().map_err(|_| format!("invalid rate limit '{}'", value))),
+            None => rate.max(1.0),
+        };
+
+        if rate <= 0.0 || burst < 1.0 {
+            return Err(ProddleError::from(format!("rate limit '{}' requires a positive rate and a burst of at least 1", value)));
+        }
+
+        Ok(RateLimit {
+            rate: rate,
+            burst: burst,
+        })
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_limit: &RateLimit, now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: rate_limit.burst,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, rate_limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill);
+        let elapsed = elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64 / 1000000000.0);
+        self.tokens = (self.tokens + elapsed * rate_limit.rate).min(rate_limit.burst);
+        self.last_refill = now;
+    }
+
+    //seconds until a token is available
+    fn wait(&self, rate_limit: &RateLimit) -> f64 {
+        match self.tokens >= 1.0 {
+            true => 0.0,
+            false => (1.0 - self.tokens) / rate_limit.rate,
+        }
+    }
+}
+
+/// Token bucket limits applied globally, per destination ip address and per registered domain.
+/// Destination ip addresses are learned from previous measurements of a domain, so the first
+/// measurement of a domain is only subject to the global and domain limits.
+pub struct RateLimiter {
+    global: Option<(RateLimit, TokenBucket)>,
+    ip_address_limit: Option<RateLimit>,
+    ip_address_buckets: HashMap<String, TokenBucket>,
+    domain_limit: Option<RateLimit>,
+    domain_buckets: HashMap<String, TokenBucket>,
+    ip_addresses: HashMap<String, String>,
+    last_eviction: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(global_limit: Option<RateLimit>, ip_address_limit: Option<RateLimit>, domain_limit: Option<RateLimit>) -> RateLimiter {
+        RateLimiter::new_at(global_limit, ip_address_limit, domain_limit, Instant::now())
+    }
+
+    fn new_at(global_limit: Option<RateLimit>, ip_address_limit: Option<RateLimit>, domain_limit: Option<RateLimit>, now: Instant) -> RateLimiter {
+        RateLimiter {
+            global: global_limit.map(|rate_limit| (rate_limit, TokenBucket::new(&rate_limit, now))),
+            ip_address_limit: ip_address_limit,
+            ip_address_buckets: HashMap::new(),
+            domain_limit: domain_limit,
+            domain_buckets: HashMap::new(),
+            ip_addresses: HashMap::new(),
+            last_eviction: now,
+        }
+    }
+
+    /// Takes a token from every bucket applying to the domain, returning None if the measurement
+    /// may start immediately or otherwise how long to wait before asking again.
+    pub fn acquire(&mut self, domain: &str) -> Option<Duration> {
+        self.acquire_at(domain, Instant::now())
+    }
+
+    fn acquire_at(&mut self, domain: &str, now: Instant) -> Option<Duration> {
+        if now.duration_since(self.last_eviction).as_secs() >= EVICTION_INTERVAL_SECONDS {
+            self.evict(now);
+        }
+
+        let mut wait: f64 = 0.0;
+        if let Some((ref rate_limit, ref mut bucket)) = self.global {
+            bucket.refill(rate_limit, now);
+            wait = wait.max(bucket.wait(rate_limit));
+        }
+
+        let ip_address = self.ip_addresses.get(domain).cloned();
+        if let (Some(rate_limit), Some(ip_address)) = (self.ip_address_limit.as_ref(), ip_address.as_ref()) {
+            let bucket = self.ip_address_buckets.entry(ip_address.to_owned()).or_insert_with(|| TokenBucket::new(rate_limit, now));
+            bucket.refill(rate_limit, now);
+            wait = wait.max(bucket.wait(rate_limit));
+        }
+
+        if let Some(ref rate_limit) = self.domain_limit {
+            let bucket = self.domain_buckets.entry(registered_domain(domain)).or_insert_with(|| TokenBucket::new(rate_limit, now));
+            bucket.refill(rate_limit, now);
+            wait = wait.max(bucket.wait(rate_limit));
+        }
+
+        if wait > 0.0 {
+            return Some(Duration::new(wait as u64, ((wait - wait.floor()) * 1000000000.0) as u32));
+        }
+
+        //consume a token from each bucket only once all of them allow the measurement
+        if let Some((_, ref mut bucket)) = self.global {
+            bucket.tokens -= 1.0;
+        }
+
+        if let Some(ref ip_address) = ip_address {
+            if let Some(bucket) = self.ip_address_buckets.get_mut(ip_address) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        if let Some(bucket) = self.domain_buckets.get_mut(&registered_domain(domain)) {
+            bucket.tokens -= 1.0;
+        }
+
+        None
+    }
+
+    /// Records the ip address a domain resolved to for subsequent per ip address limits.
+    pub fn record_ip_address(&mut self, domain: &str, ip_address: &str) {
+        if self.ip_address_limit.is_some() {
+            self.ip_addresses.insert(domain.to_owned(), ip_address.to_owned());
+        }
+    }
+
+    //remove buckets that have refilled completely, they are recreated on demand
+    fn evict(&mut self, now: Instant) {
+        if let Some(ref rate_limit) = self.ip_address_limit {
+            for bucket in self.ip_address_buckets.values_mut() {
+                bucket.refill(rate_limit, now);
+            }
+
+            self.ip_address_buckets.retain(|_, bucket| bucket.tokens < rate_limit.burst);
+        }
+
+        if let Some(ref rate_limit) = self.domain_limit {
+            for bucket in self.domain_buckets.values_mut() {
+                bucket.refill(rate_limit, now);
+            }
+
+            self.domain_buckets.retain(|_, bucket| bucket.tokens < rate_limit.burst);
+        }
+
+        self.last_eviction = now;
+    }
+}
+
+/// Approximates the registered domain of a host by its last two labels, or three when the
+/// second to last label is a common second level label beneath a country code. Addresses,
+/// including bracketed ipv6 literals, are returned as is without port or path.
+pub fn registered_domain(domain: &str) -> String {
+    let authority = domain.split('/').nth(0).unwrap_or(domain);
+    let host = if authority.starts_with('[') {
+        authority[1..].split(']').nth(0).unwrap_or("")
+    } else if IpAddr::from_str(authority).is_ok() {
+        //an unbracketed ipv6 literal has colons but no port
+        authority
+    } else {
+        authority.split(':').nth(0).unwrap_or(authority)
+    }.to_lowercase();
+
+    if IpAddr::from_str(&host).is_ok() {
+        return host;
+    }
+
+    let labels: Vec<&str> = host.trim_end_matches('.').split('.').collect();
+    let count = match labels.len() {
+        length if length <= 2 => length,
+        length if labels[length - 1].len() == 2 && SECOND_LEVEL_LABELS.contains(&labels[length - 2]) => 3,
+        _ => 2,
+    };
+
+    labels[labels.len() - count..].join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after(now: Instant, seconds: f64) -> Instant {
+        now + Duration::new(seconds as u64, ((seconds - seconds.floor()) * 1000000000.0) as u32)
+    }
+
+    fn new_rate_limiter(global_limit: Option<&str>, ip_address_limit: Option<&str>, domain_limit: Option<&str>, now: Instant) -> RateLimiter {
+        let parse = |value: Option<&str>| value.map(|x| RateLimit::parse(x).unwrap());
+        RateLimiter::new_at(parse(global_limit), parse(ip_address_limit), parse(domain_limit), now)
+    }
+
+    #[test]
+    fn rate_limit_parse() {
+        let rate_limit = RateLimit::parse("5").unwrap();
+        assert_eq!((rate_limit.rate, rate_limit.burst), (5.0, 5.0));
+        let rate_limit = RateLimit::parse("0.5").unwrap();
+        assert_eq!((rate_limit.rate, rate_limit.burst), (0.5, 1.0));
+        let rate_limit = RateLimit::parse("2|10").unwrap();
+        assert_eq!((rate_limit.rate, rate_limit.burst), (2.0, 10.0));
+
+        for value in ["", "fast", "0", "-1|2", "1|0.5", "1|many"].iter() {
+            assert!(RateLimit::parse(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn token_bucket_refills_at_rate_up_to_burst() {
+        let rate_limit = RateLimit::parse("2|3").unwrap();
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(&rate_limit, now);
+        assert_eq!((bucket.tokens, bucket.wait(&rate_limit)), (3.0, 0.0));
+
+        bucket.tokens = 0.0;
+        bucket.refill(&rate_limit, after(now, 0.25));
+        assert_eq!((bucket.tokens, bucket.wait(&rate_limit)), (0.5, 0.25));
+        bucket.refill(&rate_limit, after(now, 0.5));
+        assert_eq!((bucket.tokens, bucket.wait(&rate_limit)), (1.0, 0.0));
+        bucket.refill(&rate_limit, after(now, 10.0));
+        assert_eq!(bucket.tokens, 3.0);
+    }
+
+    #[test]
+    fn global_limit_applies_to_every_domain() {
+        let now = Instant::now();
+        let mut rate_limiter = new_rate_limiter(Some("1|2"), None, None, now);
+        assert_eq!(rate_limiter.acquire_at("google.com", now), None);
+        assert_eq!(rate_limiter.acquire_at("github.com", now), None);
+        assert_eq!(rate_limiter.acquire_at("amazon.com", now), Some(Duration::from_secs(1)));
+        assert_eq!(rate_limiter.acquire_at("amazon.com", after(now, 0.5)), Some(Duration::from_millis(500)));
+        assert_eq!(rate_limiter.acquire_at("amazon.com", after(now, 1.0)), None);
+    }
+
+    #[test]
+    fn domain_limit_is_shared_by_subdomains() {
+        let now = Instant::now();
+        let mut rate_limiter = new_rate_limiter(None, None, Some("1|1"), now);
+        assert_eq!(rate_limiter.acquire_at("www.google.com", now), None);
+        assert_eq!(rate_limiter.acquire_at("maps.google.com", now), Some(Duration::from_secs(1)));
+        assert_eq!(rate_limiter.acquire_at("github.com", now), None);
+        assert_eq!(rate_limiter.acquire_at("maps.google.com", after(now, 1.0)), None);
+    }
+
+    #[test]
+    fn ip_address_limit_applies_once_addresses_are_recorded() {
+        let now = Instant::now();
+        let mut rate_limiter = new_rate_limiter(None, Some("1|1"), None, now);
+        for _ in 0..3 {
+            assert_eq!(rate_limiter.acquire_at("google.com", now), None);
+        }
+
+        rate_limiter.record_ip_address("google.com", "192.0.2.1");
+        rate_limiter.record_ip_address("google.co.uk", "192.0.2.1");
+        rate_limiter.record_ip_address("github.com", "192.0.2.2");
+        assert_eq!(rate_limiter.acquire_at("google.com", now), None);
+        assert_eq!(rate_limiter.acquire_at("google.co.uk", now), Some(Duration::from_secs(1)));
+        assert_eq!(rate_limiter.acquire_at("github.com", now), None);
+    }
+
+    #[test]
+    fn waiting_does_not_consume_tokens() {
+        let now = Instant::now();
+        let mut rate_limiter = new_rate_limiter(Some("1|5"), None, Some("1|1"), now);
+        assert_eq!(rate_limiter.acquire_at("google.com", now), None);
+        assert_eq!(rate_limiter.acquire_at("google.com", now), Some(Duration::from_secs(1)));
+        assert_eq!(rate_limiter.global.as_ref().unwrap().1.tokens, 4.0);
+    }
+
+    #[test]
+    fn full_buckets_are_evicted() {
+        let now = Instant::now();
+        let mut rate_limiter = new_rate_limiter(None, Some("1|1"), Some("1|1"), now);
+        rate_limiter.record_ip_address("google.com", "192.0.2.1");
+        assert_eq!(rate_limiter.acquire_at("google.com", now), None);
+        assert_eq!(rate_limiter.acquire_at("github.com", after(now, 0.5)), None);
+
+        rate_limiter.evict(after(now, 0.75));
+        assert_eq!((rate_limiter.ip_address_buckets.len(), rate_limiter.domain_buckets.len()), (1, 2));
+        rate_limiter.evict(after(now, 1.25));
+        assert_eq!((rate_limiter.ip_address_buckets.len(), rate_limiter.domain_buckets.len()), (0, 1));
+
+        //acquiring sweeps once the eviction interval has passed
+        let later = after(now, EVICTION_INTERVAL_SECONDS as f64 + 2.0);
+        assert_eq!(rate_limiter.acquire_at("amazon.com", later), None);
+        assert_eq!(rate_limiter.domain_buckets.keys().collect::<Vec<&String>>(), vec!["amazon.com"]);
+    }
+
+    #[test]
+    fn registered_domain_strips_subdomains_port_and_path() {
+        assert_eq!(registered_domain("google.com"), "google.com");
+        assert_eq!(registered_domain("WWW.Google.com."), "google.com");
+        assert_eq!(registered_domain("maps.google.com:8080/x"), "google.com");
+        assert_eq!(registered_domain("news.bbc.co.uk/world"), "bbc.co.uk");
+        assert_eq!(registered_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn registered_domain_keeps_addresses() {
+        assert_eq!(registered_domain("192.0.2.1"), "192.0.2.1");
+        assert_eq!(registered_domain("192.0.2.1:8080/x"), "192.0.2.1");
+        assert_eq!(registered_domain("[2001:DB8::1]:8080/x"), "2001:db8::1");
+        assert_eq!(registered_domain("[2001:db8::1]/x"), "2001:db8::1");
+        assert_eq!(registered_domain("2001:db8::1"), "2001:db8::1");
+    }
+}
@@ -0,0 +1,151 @@
+use chan::{self, Sender};
+use proddle::Heartbeat;
+use time;
+
+use client::Client;
+use config::Config;
+use executor::ExecutorStatistics;
+use metrics::Metrics;
+
+use std;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+
+/// Settings the reporting thread needs from the vantage configuration.
+#[derive(Clone, PartialEq)]
+pub struct ReportSettings {
+    pub hostname: String,
+    pub config_version: i64,
+    pub statistics_interval_seconds: u32,
+    pub heartbeat_interval_seconds: u32,
+}
+
+impl ReportSettings {
+    pub fn new(config: &Config, config_version: i64) -> ReportSettings {
+        ReportSettings {
+            hostname: config.hostname.to_owned(),
+            config_version: config_version,
+            statistics_interval_seconds: config.send_measurements_interval_seconds,
+            heartbeat_interval_seconds: config.heartbeat_interval_seconds,
+        }
+    }
+}
+
+enum Report {
+    Statistics(ExecutorStatistics),
+    Settings(ReportSettings),
+}
+
+/// Sends statistics and heartbeats to the bridge from a separate thread so a slow or
+/// unreachable bridge never delays operation execution. Statistics are kept until the bridge
+/// accepts them and are sent a final time on shutdown.
+pub struct Reporter {
+    report_tx: Sender<Report>,
+    settings: ReportSettings,
+    handle: JoinHandle<()>,
+}
+
+impl Reporter {
+    pub fn start(client: Arc<RwLock<Client>>, metrics: Arc<Mutex<Metrics>>, settings: ReportSettings) -> Reporter {
+        let (report_tx, report_rx) = chan::async();
+        let t_settings = settings.clone();
+        let handle = std::thread::spawn(move || {
+            let mut settings = t_settings;
+            let mut pending_statistics = ExecutorStatistics::default();
+            let mut statistics_tick = chan::tick_ms(settings.statistics_interval_seconds * 1000);
+            let mut heartbeat_tick = chan::tick_ms(settings.heartbeat_interval_seconds * 1000);
+            send_heartbeat(&client, &settings, &metrics);
+
+            loop {
+                let mut updated_settings = None;
+                chan_select! {
+                    report_rx.recv() -> report => {
+                        match report {
+                            Some(Report::Statistics(statistics)) => pending_statistics.merge(&statistics),
+                            Some(Report::Settings(settings)) => updated_settings = Some(settings),
+                            None => {
+                                send_statistics(&client, &settings, &mut pending_statistics);
+                                return;
+                            },
+                        }
+                    },
+                    statistics_tick.recv() => send_statistics(&client, &settings, &mut pending_statistics),
+                    heartbeat_tick.recv() => send_heartbeat(&client, &settings, &metrics),
+                }
+
+                //chan_select! borrows the tick receivers so they may only be replaced afterwards
+                if let Some(updated_settings) = updated_settings {
+                    if updated_settings.statistics_interval_seconds != settings.statistics_interval_seconds {
+                        statistics_tick = chan::tick_ms(updated_settings.statistics_interval_seconds * 1000);
+                    }
+
+                    if updated_settings.heartbeat_interval_seconds != settings.heartbeat_interval_seconds {
+                        heartbeat_tick = chan::tick_ms(updated_settings.heartbeat_interval_seconds * 1000);
+                    }
+
+                    settings = updated_settings;
+                }
+            }
+        });
+
+        Reporter {
+            report_tx: report_tx,
+            settings: settings,
+            handle: handle,
+        }
+    }
+
+    pub fn add_statistics(&self, statistics: ExecutorStatistics) {
+        self.report_tx.send(Report::Statistics(statistics));
+    }
+
+    pub fn update_settings(&mut self, settings: ReportSettings) {
+        if settings != self.settings {
+            self.settings = settings.clone();
+            self.report_tx.send(Report::Settings(settings));
+        }
+    }
+
+    /// Sends the remaining statistics and waits for the reporting thread to exit.
+    pub fn shutdown(self) {
+        let Reporter { report_tx, handle, .. } = self;
+        drop(report_tx);
+        if let Err(_) = handle.join() {
+            error!("reporting thread panicked during shutdown");
+        }
+    }
+}
+
+fn send_statistics(client: &Arc<RwLock<Client>>, settings: &ReportSettings, pending_statistics: &mut ExecutorStatistics) {
+    let result = client.write().unwrap().send_statistics(&pending_statistics.to_document(), &settings.hostname, settings.config_version);
+    match result {
+        Ok(_) => *pending_statistics = ExecutorStatistics::default(),
+        Err(e) => error!("failed to send statistics: {}", e),
+    }
+}
+
+//report liveness to the bridge
+fn send_heartbeat(client: &Arc<RwLock<Client>>, settings: &ReportSettings, metrics: &Arc<Mutex<Metrics>>) {
+    let heartbeat = {
+        let metrics = metrics.lock().unwrap();
+        Heartbeat {
+            vantage_hostname: settings.hostname.to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            uptime_seconds: time::now_utc().to_timespec().sec - metrics.start_timestamp,
+            scheduled_operations: metrics.scheduled_operations.values().sum::<usize>() as u64,
+            queue_depth: metrics.executor_status.queue_depth as u64,
+            in_flight: metrics.executor_status.in_flight as u64,
+            measurement_buffer_depth: metrics.measurement_buffer_depth as u64,
+        }
+    };
+
+    let mut client = client.write().unwrap();
+    match client.send_heartbeat(heartbeat) {
+        Ok(_) => {
+            let mut metrics = metrics.lock().unwrap();
+            metrics.last_heartbeat = Some(time::now_utc().to_timespec().sec);
+            metrics.clock_offset_milliseconds = client.clock_offset();
+        },
+        Err(e) => error!("failed to send heartbeat: {}", e),
+    }
+}
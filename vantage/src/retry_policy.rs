@@ -0,0 +1,270 @@
+use curl;
+use proddle::ProddleError;
+use rand::{self, Rng};
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+static ERROR_CATEGORIES: [ErrorCategory; 6] = [ErrorCategory::Dns, ErrorCategory::Connect, ErrorCategory::Timeout,
+    ErrorCategory::Tls, ErrorCategory::Redirect, ErrorCategory::Other];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    Dns,
+    Connect,
+    Timeout,
+    Tls,
+    Redirect,
+    Other,
+}
+
+impl ErrorCategory {
+    pub fn parse(value: &str) -> Result<ErrorCategory, ProddleError> {
+        for error_category in ERROR_CATEGORIES.iter() {
+            if error_category.as_str() == value {
+                return Ok(*error_category);
+            }
+        }
+
+        Err(ProddleError::from(format!("unknown error category '{}'", value)))
+    }
+
+    pub fn from_curl_error(error: &curl::Error) -> ErrorCategory {
+        if error.is_couldnt_resolve_host() || error.is_couldnt_resolve_proxy() {
+            ErrorCategory::Dns
+        } else if error.is_couldnt_connect() {
+            ErrorCategory::Connect
+        } else if error.is_operation_timedout() {
+            ErrorCategory::Timeout
+        } else if error.is_ssl_connect_error() || error.is_peer_failed_verification() || error.is_ssl_certproblem()
+                || error.is_ssl_cipher() || error.is_ssl_cacert() {
+            ErrorCategory::Tls
+        } else if error.is_too_many_redirects() {
+            ErrorCategory::Redirect
+        } else {
+            ErrorCategory::Other
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ErrorCategory::Dns => "dns",
+            ErrorCategory::Connect => "connect",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::Tls => "tls",
+            ErrorCategory::Redirect => "redirect",
+            ErrorCategory::Other => "other",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: i32,
+    pub backoff_base: f64,
+    pub backoff_cap: f64,
+    pub jitter: f64,
+    pub retryable: HashSet<ErrorCategory>,
+}
+
+impl RetryPolicy {
+    /// Creates a policy retrying every error category with exponential backoff starting at 10 seconds.
+    pub fn new(max_attempts: i32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts,
+            backoff_base: 10.0,
+            backoff_cap: 300.0,
+            jitter: 0.5,
+            retryable: ERROR_CATEGORIES.iter().cloned().collect(),
+        }
+    }
+
+    /// Sets a single policy field, where 'retryable' takes a ';' separated list of error categories.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), ProddleError> {
+        match name {
+            "max_attempts" => self.max_attempts = try!(value.parse::<i32>()),
+            "backoff_base" => self.backoff_base = try!(value.parse::<f64>().map_err(|_| format!("invalid backoff base '{}'", value))),
+            "backoff_cap" => self.backoff_cap = try!(value.parse::<f64>().map_err(|_| format!("invalid backoff cap '{}'", value))),
+            "jitter" => self.jitter = try!(value.parse::<f64>().map_err(|_| format!("invalid jitter '{}'", value))),
+            "retryable" => {
+                let mut retryable = HashSet::new();
+                for error_category in value.split(";").filter(|x| !x.is_empty()) {
+                    retryable.insert(try!(ErrorCategory::parse(error_category)));
+                }
+
+                self.retryable = retryable;
+            },
+            _ => return Err(ProddleError::from(format!("unknown retry policy field '{}'", name))),
+        }
+
+        if self.max_attempts < 1 || self.backoff_base < 0.0 || self.backoff_cap < 0.0 || self.jitter < 0.0 || self.jitter > 1.0 {
+            return Err(ProddleError::from(format!("invalid retry policy value '{}' for '{}'", value, name)));
+        }
+
+        Ok(())
+    }
+
+    /// Returns a copy of the policy overridden by operation parameters prefixed with 'retry_'
+    /// (ex. retry_max_attempts|5).
+    pub fn with_overrides(&self, parameters: &HashMap<String, String>) -> Result<RetryPolicy, ProddleError> {
+        let mut retry_policy = self.clone();
+        for (name, value) in parameters.iter() {
+            if name.starts_with("retry_") {
+                try!(retry_policy.set(&name["retry_".len()..], value));
+            }
+        }
+
+        Ok(retry_policy)
+    }
+
+    /// Determines whether a failed attempt, indexed from 0, should be retried.
+    pub fn should_retry(&self, attempt: i32, error_category: ErrorCategory) -> bool {
+        attempt + 1 < self.max_attempts && self.retryable.contains(&error_category)
+    }
+
+    /// Computes the delay before the attempt following 'attempt', doubling from the base up to
+    /// the cap and reduced by up to 'jitter' of its length.
+    pub fn delay(&self, attempt: i32) -> Duration {
+        let backoff = (self.backoff_base * 2f64.powi(attempt)).min(self.backoff_cap);
+        let delay = backoff * (1.0 - self.jitter * rand::thread_rng().gen::<f64>());
+        Duration::new(delay as u64, ((delay - delay.floor()) * 1000000000.0) as u32)
+    }
+}
+
+/// Retry policies for each measurement class, falling back to a default policy.
+#[derive(Clone, Debug)]
+pub struct RetryPolicies {
+    default: RetryPolicy,
+    measurement_classes: HashMap<String, RetryPolicy>,
+}
+
+impl RetryPolicies {
+    pub fn new(default: RetryPolicy) -> RetryPolicies {
+        RetryPolicies {
+            default: default,
+            measurement_classes: HashMap::new(),
+        }
+    }
+
+    /// Parses a measurement class policy of the form 'class|name=value,name=value', with
+    /// unspecified fields taken from the default policy.
+    pub fn parse_measurement_class(&mut self, value: &str) -> Result<(), ProddleError> {
+        let mut split_values = value.split("|");
+        let measurement_class = try!(split_values.nth(0).ok_or("failed to parse retry policy measurement class"));
+        let mut retry_policy = self.default.clone();
+        if let Some(fields) = split_values.nth(0) {
+            for field in fields.split(",").filter(|x| !x.is_empty()) {
+                let mut field_values = field.split("=");
+                let name = try!(field_values.nth(0).ok_or("failed to parse retry policy field name"));
+                let value = try!(field_values.nth(0).ok_or("failed to parse retry policy field value"));
+                try!(retry_policy.set(name, value));
+            }
+        }
+
+        self.measurement_classes.insert(measurement_class.to_owned(), retry_policy);
+        Ok(())
+    }
+
+    pub fn get(&self, measurement_class: &str) -> &RetryPolicy {
+        match self.measurement_classes.get(measurement_class) {
+            Some(retry_policy) => retry_policy,
+            None => &self.default,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn as_seconds(duration: Duration) -> f64 {
+        duration.as_secs() as f64 + (duration.subsec_nanos() as f64 / 1000000000.0)
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_cap() {
+        let mut retry_policy = RetryPolicy::new(10);
+        retry_policy.jitter = 0.0;
+        retry_policy.backoff_base = 1.5;
+        retry_policy.backoff_cap = 20.0;
+
+        let delays: Vec<f64> = (0..6).map(|x| as_seconds(retry_policy.delay(x))).collect();
+        assert_eq!(delays, vec![1.5, 3.0, 6.0, 12.0, 20.0, 20.0]);
+    }
+
+    #[test]
+    fn jitter_shortens_delay_by_at_most_its_fraction() {
+        let retry_policy = RetryPolicy::new(10);
+        for attempt in 0..6 {
+            let backoff = (10.0 * 2f64.powi(attempt)).min(300.0);
+            for _ in 0..100 {
+                let delay = as_seconds(retry_policy.delay(attempt));
+                assert!(delay >= backoff * 0.5 - 0.000001 && delay <= backoff, "attempt {} delay {}", attempt, delay);
+            }
+        }
+    }
+
+    #[test]
+    fn should_retry_by_error_category_and_attempt() {
+        let mut retry_policy = RetryPolicy::new(3);
+        retry_policy.set("retryable", "dns;timeout").unwrap();
+
+        assert!(retry_policy.should_retry(0, ErrorCategory::Dns));
+        assert!(retry_policy.should_retry(1, ErrorCategory::Timeout));
+        assert!(!retry_policy.should_retry(2, ErrorCategory::Dns));
+        for error_category in [ErrorCategory::Connect, ErrorCategory::Tls, ErrorCategory::Redirect, ErrorCategory::Other].iter() {
+            assert!(!retry_policy.should_retry(0, *error_category), "{:?}", error_category);
+        }
+
+        //a single attempt is never retried
+        assert!(!RetryPolicy::new(1).should_retry(0, ErrorCategory::Dns));
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        let invalid_fields = [("max_attempts", "0"), ("max_attempts", "many"), ("backoff_base", "-1"), ("backoff_cap", "soon"),
+            ("jitter", "1.5"), ("jitter", "-0.1"), ("retryable", "dns;fire"), ("timeout", "10")];
+        for &(name, value) in invalid_fields.iter() {
+            assert!(RetryPolicy::new(3).set(name, value).is_err(), "{}={}", name, value);
+        }
+
+        let mut retry_policy = RetryPolicy::new(3);
+        retry_policy.set("retryable", "").unwrap();
+        assert!(retry_policy.retryable.is_empty());
+    }
+
+    #[test]
+    fn overrides_apply_retry_parameters_only() {
+        let mut parameters = HashMap::new();
+        parameters.insert(String::from("retry_max_attempts"), String::from("5"));
+        parameters.insert(String::from("retry_backoff_base"), String::from("2"));
+        parameters.insert(String::from("timeout"), String::from("30"));
+
+        let default = RetryPolicy::new(3);
+        let retry_policy = default.with_overrides(&parameters).unwrap();
+        assert_eq!((retry_policy.max_attempts, retry_policy.backoff_base), (5, 2.0));
+        assert_eq!((retry_policy.backoff_cap, retry_policy.jitter), (default.backoff_cap, default.jitter));
+        assert_eq!(default.max_attempts, 3);
+
+        parameters.insert(String::from("retry_jitter"), String::from("2"));
+        assert!(default.with_overrides(&parameters).is_err());
+    }
+
+    #[test]
+    fn measurement_class_policies_extend_the_default() {
+        let mut retry_policies = RetryPolicies::new(RetryPolicy::new(3));
+        retry_policies.parse_measurement_class("HttpGet|max_attempts=5,retryable=timeout").unwrap();
+        retry_policies.parse_measurement_class("Ping").unwrap();
+
+        let retry_policy = retry_policies.get("HttpGet");
+        assert_eq!(retry_policy.max_attempts, 5);
+        assert_eq!(retry_policy.backoff_base, 10.0);
+        assert_eq!(retry_policy.retryable.iter().cloned().collect::<Vec<ErrorCategory>>(), vec![ErrorCategory::Timeout]);
+        assert_eq!(retry_policies.get("Ping").max_attempts, 3);
+        assert_eq!(retry_policies.get("Traceroute").max_attempts, 3);
+
+        for value in ["HttpGet|max_attempts", "HttpGet|max_attempts=0", "HttpGet|delay=5"].iter() {
+            assert!(retry_policies.parse_measurement_class(value).is_err(), "{}", value);
+        }
+    }
+}
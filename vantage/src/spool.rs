@@ -0,0 +1,54 @@
+use bson::{self, Document};
+use proddle::ProddleError;
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// Reads measurements spooled by a previous shutdown, returning an empty vector if the file does
+/// not exist.
+pub fn read(path: &str) -> Result<Vec<Document>, ProddleError> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(ProddleError::from(e)),
+    };
+
+    let mut buffer = Vec::new();
+    try!(file.read_to_end(&mut buffer));
+
+    let length = buffer.len() as u64;
+    let mut cursor = Cursor::new(buffer);
+    let mut documents = Vec::new();
+    while cursor.position() < length {
+        documents.push(try!(bson::decode_document(&mut cursor)));
+    }
+
+    Ok(documents)
+}
+
+/// Writes measurements to the spool file, replacing its contents. The file is written beside
+/// the destination and renamed so an interrupted write never truncates existing measurements.
+pub fn write(path: &str, documents: &Vec<Document>) -> Result<(), ProddleError> {
+    let temporary_path = format!("{}.tmp", path);
+    {
+        let mut writer = BufWriter::new(try!(File::create(&temporary_path)));
+        for document in documents.iter() {
+            try!(bson::encode_document(&mut writer, document));
+        }
+
+        try!(writer.flush());
+        try!(try!(writer.into_inner().map_err(|e| format!("failed to flush spool file '{}': {}", temporary_path, e.error()))).sync_all());
+    }
+
+    try!(fs::rename(&temporary_path, path));
+    Ok(())
+}
+
+/// Removes the spool file once its measurements have been delivered.
+pub fn remove(path: &str) -> Result<(), ProddleError> {
+    match Path::new(path).exists() {
+        true => Ok(try!(fs::remove_file(path))),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,138 @@
+use proddle::ProddleError;
+use rand::{self, Rng};
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+
+/// Reads the vantage id persisted at 'path', generating and persisting a random (version 4
+/// uuid) id on first run. The id identifies the vantage independently of its hostname, so a
+/// missing file after a previous run is an error rather than silently becoming a new vantage.
+/// Previous runs are recorded by a marker file beside the id file.
+pub fn load_or_create(path: &str) -> Result<String, ProddleError> {
+    let marker_path = format!("{}.created", path);
+    match File::open(path) {
+        Ok(mut file) => {
+            let mut vantage_id = String::new();
+            try!(file.read_to_string(&mut vantage_id));
+
+            let vantage_id = vantage_id.trim().to_owned();
+            if !is_valid(&vantage_id) {
+                return Err(ProddleError::from(format!("invalid vantage id '{}' in '{}'", vantage_id, path)));
+            }
+
+            //id files written before markers existed are marked on their next load
+            if !Path::new(&marker_path).exists() {
+                try!(File::create(&marker_path));
+            }
+
+            Ok(vantage_id)
+        },
+        Err(ref e) if e.kind() == ErrorKind::NotFound && Path::new(&marker_path).exists() => {
+            Err(ProddleError::from(format!("vantage id file '{}' is missing but the vantage has run before, restore it to keep the vantage id", path)))
+        },
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            if let Some(directory) = Path::new(path).parent() {
+                try!(fs::create_dir_all(directory));
+            }
+
+            let vantage_id = generate();
+            let mut file = try!(File::create(path));
+            try!(file.write_all(format!("{}\n", vantage_id).as_bytes()));
+            try!(file.sync_all());
+            try!(File::create(&marker_path));
+
+            info!("generated vantage id '{}' in '{}'", vantage_id, path);
+            Ok(vantage_id)
+        },
+        Err(e) => Err(ProddleError::from(e)),
+    }
+}
+
+fn generate() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: Vec<String> = bytes.iter().map(|x| format!("{:02x}", x)).collect();
+    format!("{}-{}-{}-{}-{}", hex[0..4].concat(), hex[4..6].concat(), hex[6..8].concat(), hex[8..10].concat(), hex[10..16].concat())
+}
+
+fn is_valid(vantage_id: &str) -> bool {
+    let groups: Vec<&str> = vantage_id.split('-').collect();
+    groups.len() == 5
+        && groups.iter().zip([8, 4, 4, 4, 12].iter()).all(|(group, length)| group.len() == *length)
+        && groups.iter().all(|group| group.chars().all(|c| c.is_digit(16)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn temp_id_file(name: &str) -> String {
+        let directory = env::temp_dir().join(format!("proddle-vantage-id-{}-{}", name, rand::random::<u32>()));
+        directory.join("vantage.id").to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn generated_ids_are_version_4_uuids() {
+        for _ in 0..100 {
+            let vantage_id = generate();
+            assert!(is_valid(&vantage_id), "{}", vantage_id);
+
+            let groups: Vec<&str> = vantage_id.split('-').collect();
+            assert!(groups[2].starts_with('4'), "{}", vantage_id);
+            assert!(groups[3].starts_with(|c: char| "89ab".contains(c)), "{}", vantage_id);
+        }
+
+        assert!(generate() != generate());
+    }
+
+    #[test]
+    fn validity_checks_group_lengths_and_digits() {
+        let valid_ids = ["01234567-89ab-4cde-8f01-23456789abcd", "FFFFFFFF-FFFF-4FFF-BFFF-FFFFFFFFFFFF"];
+        for vantage_id in valid_ids.iter() {
+            assert!(is_valid(vantage_id), "{}", vantage_id);
+        }
+
+        let invalid_ids = ["", "01234567-89ab-4cde-8f01", "01234567-89ab-4cde-8f01-23456789abcd-0123",
+            "0123456-789ab-4cde-8f01-23456789abcd", "01234567-89ab-4cde-8f01-23456789abcg", "01234567_89ab_4cde_8f01_23456789abcd"];
+        for vantage_id in invalid_ids.iter() {
+            assert!(!is_valid(vantage_id), "{}", vantage_id);
+        }
+    }
+
+    #[test]
+    fn id_is_created_once_and_reloaded() {
+        let path = temp_id_file("reload");
+        let vantage_id = load_or_create(&path).unwrap();
+        assert!(is_valid(&vantage_id));
+        assert_eq!(load_or_create(&path).unwrap(), vantage_id);
+
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_id_after_previous_run_is_an_error() {
+        let path = temp_id_file("missing");
+        load_or_create(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(load_or_create(&path).is_err());
+        assert!(!Path::new(&path).exists());
+
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn invalid_id_is_an_error() {
+        let path = temp_id_file("invalid");
+        fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        File::create(&path).unwrap().write_all(b"not-a-vantage-id\n").unwrap();
+        assert!(load_or_create(&path).is_err());
+
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+}
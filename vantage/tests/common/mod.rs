@@ -0,0 +1,187 @@
+#![allow(dead_code)]
+
+use bridge::Bridge;
+use bridge::metrics::Metrics;
+use bridge::store::MemoryStore;
+use proddle::Operation;
+
+use std;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub static TIMEOUT_SECONDS: u64 = 60;
+
+//http server counting the requests it has answered, each one is a measurement the vantage owes
+pub struct HttpServer {
+    pub port: u16,
+    pub served: Arc<AtomicUsize>,
+}
+
+impl HttpServer {
+    pub fn start() -> HttpServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let served = Arc::new(AtomicUsize::new(0));
+
+        let t_served = served.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+
+                let t_served = t_served.clone();
+                thread::spawn(move || {
+                    let mut request = Vec::new();
+                    let mut buffer = [0u8; 1024];
+                    while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+                        match stream.read(&mut buffer) {
+                            Ok(0) | Err(_) => return,
+                            Ok(length) => request.extend_from_slice(&buffer[..length]),
+                        }
+                    }
+
+                    let response = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+                    if stream.write_all(response).and_then(|_| stream.flush()).is_ok() {
+                        t_served.fetch_add(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+
+        HttpServer {
+            port: port,
+            served: served,
+        }
+    }
+
+    pub fn served(&self) -> usize {
+        self.served.load(Ordering::SeqCst)
+    }
+
+    pub fn domain(&self, path: usize) -> String {
+        format!("127.0.0.1:{}/{}", self.port, path)
+    }
+}
+
+//operations against paths of the http server carrying a single tag
+pub fn operations(http_server: &HttpServer, tag: &str) -> Vec<Operation> {
+    (0..3).map(|i| {
+        Operation {
+            version: 0,
+            deleted: false,
+            timestamp: 0,
+            measurement_class: String::from("HttpGet"),
+            domain: http_server.domain(i),
+            parameters: Vec::new(),
+            tags: vec![tag.to_owned()],
+            schedule: None,
+            start_timestamp: None,
+            end_timestamp: None,
+        }
+    }).collect()
+}
+
+//unique per process so concurrently running tests do not share files
+pub fn temp_path(name: &str, extension: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("vantage-{}-{}.{}", name, std::process::id(), extension));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+//start a vantage with a single bucket, its id file is kept alongside the spool file
+pub fn spawn_vantage(hostname: &str, bridge_port: u16, spool_file: &PathBuf, args: &[&str]) -> Child {
+    let id_file = spool_file.with_extension("id");
+    Command::new(env!("CARGO_BIN_EXE_vantage"))
+        .args(&["-H", hostname, "-b", "1", "-i", "127.0.0.1", "-p", &bridge_port.to_string()])
+        .args(&["--spool_file", spool_file.to_str().unwrap(), "--id_file", id_file.to_str().unwrap()])
+        .args(args)
+        .spawn()
+        .unwrap()
+}
+
+//bridge running in process on an ephemeral port, the store handle shares its data
+pub fn start_bridge(store: &MemoryStore) -> Bridge {
+    let socket_addr = SocketAddr::from_str("127.0.0.1:0").unwrap();
+    Bridge::start(socket_addr, Box::new(store.clone()), Arc::new(Mutex::new(Metrics::new()))).unwrap()
+}
+
+//vantage measuring an http server through an in process bridge, named 'e2e-<name>' and
+//scheduling the operations tagged '<name>' if its arguments include them
+pub struct EndToEnd {
+    pub http_server: HttpServer,
+    pub store: MemoryStore,
+    pub bridge: Option<Bridge>,
+    pub vantage: Child,
+    pub spool_file: PathBuf,
+}
+
+impl EndToEnd {
+    //'configure' prepares the store after the operations are added and before the bridge starts
+    pub fn start<F: FnOnce(&MemoryStore)>(name: &str, args: &[&str], configure: F) -> EndToEnd {
+        let http_server = HttpServer::start();
+        let store = MemoryStore::new();
+        for operation in operations(&http_server, name) {
+            store.add_operation(operation);
+        }
+
+        configure(&store);
+        let bridge = start_bridge(&store);
+        let hostname = format!("e2e-{}", name);
+        let spool_file = temp_path(&hostname, "spool");
+        let vantage = spawn_vantage(&hostname, bridge.local_addr().port(), &spool_file, args);
+
+        EndToEnd {
+            http_server: http_server,
+            store: store,
+            bridge: Some(bridge),
+            vantage: vantage,
+            spool_file: spool_file,
+        }
+    }
+
+    //the vantage must exit cleanly on SIGTERM and the bridge shut down once it has
+    pub fn stop(&mut self) {
+        let status = terminate(&mut self.vantage);
+        assert!(status.success(), "vantage exited with {}", status);
+        if let Some(bridge) = self.bridge.take() {
+            assert!(bridge.shutdown());
+        }
+    }
+}
+
+pub fn wait_until<F: Fn() -> bool>(condition: F) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(TIMEOUT_SECONDS), "timed out waiting for vantage");
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+pub fn terminate(child: &mut Child) -> ExitStatus {
+    let status = Command::new("kill").args(&["-TERM", &child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+
+        if start.elapsed() > Duration::from_secs(TIMEOUT_SECONDS) {
+            let _ = child.kill();
+            panic!("vantage did not exit after SIGTERM");
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,188 @@
+extern crate bridge;
+extern crate bson;
+extern crate proddle;
+
+mod common;
+
+use bridge::Bridge;
+use bridge::store::MemoryStore;
+use common::{EndToEnd, start_bridge, wait_until};
+use proddle::{ClockSample, Heartbeat, Message, MessageType, VantageConfig};
+
+use std::collections::HashMap;
+use std::net::TcpStream;
+
+//send a heartbeat as a vantage would, reporting a clock sample from a previous exchange
+fn send_heartbeat(bridge: &Bridge, scheduled_operations: u64, clock_sample: Option<ClockSample>) -> Message {
+    let heartbeat = Heartbeat {
+        vantage_hostname: String::from("e2e-heartbeat"),
+        version: String::from("0.0.0"),
+        uptime_seconds: 10,
+        scheduled_operations: scheduled_operations,
+        queue_depth: 1,
+        in_flight: 2,
+        measurement_buffer_depth: 3,
+    };
+
+    let request = Message::heartbeat_request(heartbeat).with_vantage_id("e2e-heartbeat-id")
+        .with_clock_sample(clock_sample).with_timestamps(None, proddle::timestamp_milliseconds());
+    let mut stream = TcpStream::connect(bridge.local_addr()).unwrap();
+    proddle::message_to_stream(&request, &mut stream).unwrap();
+    proddle::message_from_stream(&mut stream).unwrap()
+}
+
+fn measured_domains(store: &MemoryStore) -> Vec<String> {
+    let mut domains: Vec<String> = store.measurements().iter()
+        .map(|x| x.get_str("measurement_domain").unwrap().to_owned()).collect();
+    domains.sort();
+    domains.dedup();
+    domains
+}
+
+fn scheduled_operations(store: &MemoryStore) -> Option<u64> {
+    store.vantages().values().next().map(|x| x.heartbeat.scheduled_operations)
+}
+
+#[test]
+fn measurements_are_stored_with_vantage_and_clock_fields() {
+    let mut e2e = EndToEnd::start("stored", &["-t", "stored|1", "-s", "1"], |_| {});
+    wait_until(|| measured_domains(&e2e.store).len() == 3);
+    e2e.stop();
+
+    let expected_domains: Vec<String> = (0..3).map(|i| e2e.http_server.domain(i)).collect();
+    assert_eq!(measured_domains(&e2e.store), expected_domains);
+
+    let vantages = e2e.store.vantages();
+    assert_eq!(vantages.len(), 1);
+    let (vantage_id, vantage_record) = vantages.iter().next().unwrap();
+    assert_eq!(vantage_record.heartbeat.vantage_hostname, "e2e-stored");
+
+    for measurement in e2e.store.measurements() {
+        assert_eq!(measurement.get_str("measurement_class").unwrap(), "HttpGet");
+        assert_eq!(measurement.get_str("vantage_hostname").unwrap(), "e2e-stored");
+        assert_eq!(measurement.get_str("vantage_id").unwrap(), vantage_id.as_str());
+
+        //the clock offset is sampled by the exchanges preceding the first measurement upload
+        let start = measurement.get_i64("start_timestamp_milliseconds").unwrap();
+        let end = measurement.get_i64("end_timestamp_milliseconds").unwrap();
+        let clock_offset = measurement.get_i64("clock_offset_milliseconds").unwrap();
+        assert!(start <= end);
+        assert_eq!(measurement.get_i64("corrected_start_timestamp_milliseconds").unwrap(), start + clock_offset);
+        assert_eq!(measurement.get_i64("corrected_end_timestamp_milliseconds").unwrap(), end + clock_offset);
+    }
+
+    assert!(e2e.store.statistics().len() > 0);
+}
+
+#[test]
+fn measurements_failing_to_insert_are_resent() {
+    //the first measurements sent are reported as failed and must be sent again
+    let mut e2e = EndToEnd::start("failing", &["-t", "failing|1", "-s", "1"], |store| store.fail_measurement_inserts(2));
+    wait_until(|| e2e.store.measurements().len() >= 3);
+    e2e.stop();
+
+    let (stored, served) = (e2e.store.measurements().len(), e2e.http_server.served());
+    assert!(stored >= served, "stored {} of {} measurements", stored, served);
+    assert!(!e2e.spool_file.exists());
+}
+
+#[test]
+fn bridge_configuration_selects_operations() {
+    //the vantage only includes a tag no operation carries until the bridge configuration applies
+    let mut include_tags = HashMap::new();
+    include_tags.insert(String::from("pushed"), 1);
+    let vantage_config = VantageConfig {
+        hostname: Some(String::from("e2e-pushed")),
+        timestamp: 1,
+        include_tags: Some(include_tags),
+        ..VantageConfig::default()
+    };
+    let config_version = vantage_config.content_version();
+
+    let mut e2e = EndToEnd::start("pushed", &["-t", "unused|1", "-s", "1"], |store| store.set_vantage_config(vantage_config));
+    wait_until(|| measured_domains(&e2e.store).len() == 3);
+    e2e.stop();
+
+    //the configuration is applied by the first update, before any statistics are sent
+    for statistics in e2e.store.statistics() {
+        assert_eq!(statistics.get_i64("config_version").unwrap(), config_version);
+    }
+}
+
+#[test]
+fn deleted_group_configuration_is_removed() {
+    //the older group configuration selects the operations, the newer one only sets jitter
+    let args = ["-G", "selecting", "-G", "jitter", "-t", "unused|1", "-s", "1", "-u", "1", "--heartbeat_interval_seconds", "1"];
+    let mut e2e = EndToEnd::start("grouped", &args, |store| {
+        let mut include_tags = HashMap::new();
+        include_tags.insert(String::from("grouped"), 1);
+        store.set_vantage_config(VantageConfig {
+            group: Some(String::from("selecting")),
+            timestamp: 1,
+            include_tags: Some(include_tags),
+            ..VantageConfig::default()
+        });
+        store.set_vantage_config(VantageConfig {
+            group: Some(String::from("jitter")),
+            timestamp: 2,
+            max_jitter_seconds: Some(0),
+            ..VantageConfig::default()
+        });
+    });
+    wait_until(|| scheduled_operations(&e2e.store) == Some(3));
+
+    //deleting the configuration which is not the newest still changes the pushed version
+    assert!(e2e.store.delete_vantage_config(None, Some("selecting")));
+    wait_until(|| scheduled_operations(&e2e.store) == Some(0));
+    e2e.stop();
+}
+
+#[test]
+fn deleted_operations_are_removed_by_delta() {
+    let mut e2e = EndToEnd::start("delta", &["-t", "delta|1", "-s", "1", "-u", "1", "--heartbeat_interval_seconds", "1"], |_| {});
+    wait_until(|| scheduled_operations(&e2e.store) == Some(3));
+
+    //the vantage has synchronized a version so the deletion arrives as a tombstone
+    assert!(e2e.store.delete_operation("HttpGet", &e2e.http_server.domain(0)));
+    wait_until(|| scheduled_operations(&e2e.store) == Some(2));
+    e2e.stop();
+}
+
+#[test]
+fn heartbeats_are_recorded_by_the_bridge() {
+    let store = MemoryStore::new();
+    let bridge = start_bridge(&store);
+
+    let response = send_heartbeat(&bridge, 5, None);
+    match response.message_type {
+        MessageType::HeartbeatResponse => {},
+        message_type => panic!("unexpected response {:?}", message_type),
+    }
+    assert!(response.receive_timestamp.is_some() && response.transmit_timestamp.is_some());
+
+    let vantage_record = store.vantages()["e2e-heartbeat-id"].clone();
+    assert_eq!(vantage_record.ip_address, "127.0.0.1");
+    assert_eq!(vantage_record.heartbeat.vantage_hostname, "e2e-heartbeat");
+    assert_eq!(vantage_record.heartbeat.scheduled_operations, 5);
+    assert_eq!(vantage_record.clock_offset_milliseconds, None);
+    assert_eq!(vantage_record.first_heartbeat, vantage_record.last_heartbeat);
+
+    //a later heartbeat replaces the record but keeps the first heartbeat time
+    let clock_sample = ClockSample { originate: 0, receive: 1050, transmit: 1070, destination: 120 };
+    send_heartbeat(&bridge, 7, Some(clock_sample));
+    let updated_record = store.vantages()["e2e-heartbeat-id"].clone();
+    assert_eq!(store.vantages().len(), 1);
+    assert_eq!(updated_record.heartbeat.scheduled_operations, 7);
+    assert_eq!(updated_record.clock_offset_milliseconds, Some(1000));
+    assert_eq!(updated_record.first_heartbeat, vantage_record.first_heartbeat);
+    assert!(updated_record.last_heartbeat >= vantage_record.last_heartbeat);
+
+    //heartbeats without a vantage id are not recorded
+    let request = Message::heartbeat_request(updated_record.heartbeat.clone());
+    let mut stream = TcpStream::connect(bridge.local_addr()).unwrap();
+    proddle::message_to_stream(&request, &mut stream).unwrap();
+    assert!(proddle::message_from_stream(&mut stream).is_err());
+    assert_eq!(store.vantages().len(), 1);
+
+    assert!(bridge.shutdown());
+}
@@ -0,0 +1,148 @@
+extern crate bridge;
+extern crate bson;
+extern crate proddle;
+
+mod common;
+
+use bson::Document;
+use common::{HttpServer, operations, terminate, wait_until};
+use proddle::{Message, MessageType, Operation};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+static TAG: &'static str = "shutdown-test";
+
+//bridge stand-in serving fixed operations and recording the measurements it acknowledges
+struct FakeBridge {
+    port: u16,
+    update_count: Arc<AtomicUsize>,
+    measurements: Arc<Mutex<Vec<Document>>>,
+}
+
+impl FakeBridge {
+    fn start(operations: Vec<Operation>, accept_measurements: bool) -> FakeBridge {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let update_count = Arc::new(AtomicUsize::new(0));
+        let measurements = Arc::new(Mutex::new(Vec::new()));
+
+        let (t_update_count, t_measurements) = (update_count.clone(), measurements.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(mut stream) = stream {
+                    handle_stream(&mut stream, &operations, accept_measurements, &t_update_count, &t_measurements);
+                }
+            }
+        });
+
+        FakeBridge {
+            port: port,
+            update_count: update_count,
+            measurements: measurements,
+        }
+    }
+
+    fn measurement_count(&self) -> usize {
+        self.measurements.lock().unwrap().len()
+    }
+}
+
+fn handle_stream(stream: &mut TcpStream, operations: &Vec<Operation>, accept_measurements: bool,
+                 update_count: &AtomicUsize, measurements: &Mutex<Vec<Document>>) {
+    let request = match proddle::message_from_stream(stream) {
+        Ok(request) => request,
+        Err(_) => return,
+    };
+
+    let response = match request.message_type {
+        MessageType::UpdateOperationsRequest => {
+            update_count.fetch_add(1, Ordering::SeqCst);
+            let mut operation_buckets = HashMap::new();
+            operation_buckets.insert(0, operations.clone());
+            Message::update_operations_response(operation_buckets, None, 0, None, None)
+        },
+        MessageType::SendMeasurementsRequest if accept_measurements => {
+            let mut measurements = measurements.lock().unwrap();
+            for measurement in request.send_measurements_request.unwrap() {
+                measurements.push(bson::decode_document(&mut Cursor::new(measurement)).unwrap());
+            }
+
+            Message::send_measurements_response(Vec::new())
+        },
+        MessageType::SendMeasurementsRequest => Message::error(String::from("measurements rejected")),
+        MessageType::SendStatisticsRequest => Message::send_statistics_response(),
+        _ => Message::error(format!("unsupported message type: '{:?}'", request.message_type)),
+    };
+
+    let _ = proddle::message_to_stream(&response, stream);
+}
+
+fn read_spool_file(path: &PathBuf) -> Vec<Document> {
+    let mut buffer = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut buffer).unwrap();
+
+    let length = buffer.len() as u64;
+    let mut cursor = Cursor::new(buffer);
+    let mut documents = Vec::new();
+    while cursor.position() < length {
+        documents.push(bson::decode_document(&mut cursor).unwrap());
+    }
+
+    documents
+}
+
+//measurements are only sent on shutdown so every one of them depends on the flush
+fn spawn_vantage(bridge: &FakeBridge, spool_file: &PathBuf) -> Child {
+    common::spawn_vantage("shutdown-test", bridge.port, spool_file, &["-t", &format!("{}|1", TAG), "-u", "3600", "-s", "3600"])
+}
+
+#[test]
+fn shutdown_flushes_measurements_to_bridge() {
+    let http_server = HttpServer::start();
+    let bridge = FakeBridge::start(operations(&http_server, TAG), true);
+    let spool_file = common::temp_path("flush", "spool");
+
+    let mut vantage = spawn_vantage(&bridge, &spool_file);
+    wait_until(|| http_server.served() >= 3);
+    let status = terminate(&mut vantage);
+
+    assert!(status.success(), "vantage exited with {}", status);
+    let served = http_server.served();
+    assert!(bridge.measurement_count() >= served, "bridge received {} of {} measurements", bridge.measurement_count(), served);
+    assert!(!spool_file.exists());
+}
+
+#[test]
+fn shutdown_spools_measurements_and_resends_them_on_startup() {
+    let http_server = HttpServer::start();
+    let rejecting_bridge = FakeBridge::start(operations(&http_server, TAG), false);
+    let spool_file = common::temp_path("spool", "spool");
+
+    //measurements the bridge refuses are persisted on shutdown
+    let mut vantage = spawn_vantage(&rejecting_bridge, &spool_file);
+    wait_until(|| http_server.served() >= 3);
+    let status = terminate(&mut vantage);
+
+    assert!(status.success(), "vantage exited with {}", status);
+    let served = http_server.served();
+    let spooled = read_spool_file(&spool_file).len();
+    assert!(spooled >= served, "spooled {} of {} measurements", spooled, served);
+
+    //and delivered by the next run
+    let bridge = FakeBridge::start(Vec::new(), true);
+    let mut vantage = spawn_vantage(&bridge, &spool_file);
+    wait_until(|| bridge.update_count.load(Ordering::SeqCst) >= 1);
+    let status = terminate(&mut vantage);
+
+    assert!(status.success(), "vantage exited with {}", status);
+    assert_eq!(bridge.measurement_count(), spooled);
+    assert!(!spool_file.exists());
+}
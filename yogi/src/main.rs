@@ -1,3 +1,4 @@
+#[macro_use(bson, doc)]
 extern crate bson;
 #[macro_use]
 extern crate clap;
@@ -12,6 +13,7 @@ use mongodb::db::ThreadedDatabase;
 use proddle::ProddleError;
 
 mod operation;
+mod vantage_config;
 
 fn parse_args(matches: &ArgMatches) -> Result<(String, u16, String, String, String, String, String), ProddleError> {
     let mongodb_ip_address = try!(value_t!(matches, "MONGODB_IP_ADDRESS", String));
@@ -56,9 +58,21 @@ fn main() {
     let result = if let Some(matches) = matches.subcommand_matches("operation") {
         if let Some(matches) = matches.subcommand_matches("add") {
             operation::add(&db, matches)
+        } else if let Some(matches) = matches.subcommand_matches("delete") {
+            operation::delete(&db, matches)
+        } else if let Some(matches) = matches.subcommand_matches("purge") {
+            operation::purge(&db, matches)
         } else {
             panic!("operation unreachable");
         }
+    } else if let Some(matches) = matches.subcommand_matches("config") {
+        if let Some(matches) = matches.subcommand_matches("set") {
+            vantage_config::set(&db, matches)
+        } else if let Some(matches) = matches.subcommand_matches("delete") {
+            vantage_config::delete(&db, matches)
+        } else {
+            panic!("config unreachable");
+        }
     } else {
         panic!("unreachable");
     };
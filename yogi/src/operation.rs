@@ -1,9 +1,15 @@
 use bson::{self, Bson};
 use clap::ArgMatches;
+use mongodb::coll::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument, UpdateOptions};
 use mongodb::db::{Database, ThreadedDatabase};
-use proddle::{Operation, Parameter, ProddleError};
+use proddle::{Operation, Parameter, ProddleError, Schedule};
 use time;
 
+//ids of the documents in the counters collection holding the latest operation version and the
+//version up to which tombstones may have been purged
+static VERSION_COUNTER: &'static str = "operation_version";
+static PURGED_VERSION_COUNTER: &'static str = "purged_operation_version";
+
 pub fn add(db: &Database, matches: &ArgMatches) -> Result<(), ProddleError> {
     let measurement_class = try!(value_t!(matches, "MEASUREMENT_CLASS", String));
     let domain = try!(value_t!(matches, "DOMAIN", String));
@@ -33,22 +39,142 @@ pub fn add(db: &Database, matches: &ArgMatches) -> Result<(), ProddleError> {
         None => Vec::new(),
     };
 
+    //validate schedule and window
+    let schedule = match matches.value_of("SCHEDULE") {
+        Some(schedule) => {
+            try!(Schedule::parse(schedule));
+            Some(schedule.to_owned())
+        },
+        None => None,
+    };
+
+    let start_timestamp = match matches.value_of("START_TIMESTAMP") {
+        Some(_) => Some(try!(value_t!(matches, "START_TIMESTAMP", i64))),
+        None => None,
+    };
+
+    let end_timestamp = match matches.value_of("END_TIMESTAMP") {
+        Some(_) => Some(try!(value_t!(matches, "END_TIMESTAMP", i64))),
+        None => None,
+    };
+
+    if let (Some(start_timestamp), Some(end_timestamp)) = (start_timestamp, end_timestamp) {
+        if start_timestamp > end_timestamp {
+            return Err(ProddleError::from("start timestamp must not be later than end timestamp"));
+        }
+    }
+
     //create opeation document
     let timestamp = time::now_utc().to_timespec().sec;
     let operation = Operation {
+        version: try!(next_version(db)),
+        deleted: false,
         timestamp: timestamp,
         measurement_class: measurement_class,
         domain: domain,
         parameters: parameters,
         tags: tags,
+        schedule: schedule,
+        start_timestamp: start_timestamp,
+        end_timestamp: end_timestamp,
     };
 
-    //insert document
+    //replace any existing operation or tombstone with the same measurement class and domain
+    let filter = doc!("measurement_class" => operation.measurement_class.to_owned(), "domain" => operation.domain.to_owned());
     if let Bson::Document(document) = try!(bson::to_bson(&operation)) {
-        try!(db.collection("operations").insert_one(document, None));
+        let mut replace_options = UpdateOptions::new();
+        replace_options.upsert = Some(true);
+        try!(db.collection("operations").replace_one(filter, document, Some(replace_options)));
     } else {
         return Err(ProddleError::from("failed to parse Operation into OrdererdDocument"));
     }
 
     Ok(())
 }
+
+pub fn delete(db: &Database, matches: &ArgMatches) -> Result<(), ProddleError> {
+    let domain = try!(value_t!(matches, "DOMAIN", String));
+
+    //operations are kept as tombstones so vantages synchronizing a delta remove them
+    let filter = doc!("domain" => domain.to_owned(), "deleted" => Bson::Document(doc!("$ne" => true)));
+    let cursor = try!(db.collection("operations").find(Some(filter), None));
+    let mut deleted_count = 0;
+    for document in cursor {
+        let document = try!(document);
+        let id = try!(document.get_object_id("_id").map_err(|e| format!("invalid operation id: {}", e))).clone();
+        let version = try!(next_version(db));
+        let update = doc!("$set" => Bson::Document(doc!("deleted" => true, "version" => version)));
+        try!(db.collection("operations").update_one(doc!("_id" => Bson::ObjectId(id)), update, None));
+        deleted_count += 1;
+    }
+
+    if deleted_count == 0 {
+        return Err(ProddleError::from(format!("no operations found for domain '{}'", domain)));
+    }
+
+    Ok(())
+}
+
+pub fn purge(db: &Database, matches: &ArgMatches) -> Result<(), ProddleError> {
+    let versions = try!(value_t!(matches, "VERSIONS", i64));
+    if versions < 0 {
+        return Err(ProddleError::from("versions must not be negative"));
+    }
+
+    let purged_version = try!(current_version(db)) - versions;
+    if purged_version <= 0 {
+        return Ok(());
+    }
+
+    //the purged version is recorded first so bridges never send a delta missing removed tombstones
+    let mut update_options = UpdateOptions::new();
+    update_options.upsert = Some(true);
+    try!(db.collection("counters").update_one(doc!("_id" => PURGED_VERSION_COUNTER),
+        doc!("$max" => Bson::Document(doc!("value" => purged_version))), Some(update_options)));
+
+    let filter = doc!("deleted" => true, "version" => Bson::Document(doc!("$lte" => purged_version)));
+    try!(db.collection("operations").delete_many(filter, None));
+    Ok(())
+}
+
+//versions increase with every change and are taken from a counter document so concurrent
+//changes never share a version, operations written before versioning count as version 0
+fn next_version(db: &Database) -> Result<i64, ProddleError> {
+    for _ in 0..2 {
+        let mut find_options = FindOneAndUpdateOptions::new();
+        find_options.return_document = Some(ReturnDocument::After);
+        let update = doc!("$inc" => Bson::Document(doc!("value" => 1i64)));
+        match try!(db.collection("counters").find_one_and_update(doc!("_id" => VERSION_COUNTER), update, Some(find_options))) {
+            Some(document) => return Ok(try!(document.get_i64("value").map_err(|e| format!("invalid version counter: {}", e)))),
+            None => try!(create_version_counter(db)),
+        }
+    }
+
+    Err(ProddleError::from("failed to create version counter"))
+}
+
+fn current_version(db: &Database) -> Result<i64, ProddleError> {
+    for _ in 0..2 {
+        match try!(db.collection("counters").find_one(Some(doc!("_id" => VERSION_COUNTER)), None)) {
+            Some(document) => return Ok(try!(document.get_i64("value").map_err(|e| format!("invalid version counter: {}", e)))),
+            None => try!(create_version_counter(db)),
+        }
+    }
+
+    Err(ProddleError::from("failed to create version counter"))
+}
+
+//start the counter at the highest existing version, an insert failing because another process
+//created the counter first is ignored
+fn create_version_counter(db: &Database) -> Result<(), ProddleError> {
+    let negative_one = -1;
+    let mut find_options = FindOptions::new();
+    find_options.sort = Some(doc!("version" => negative_one));
+    let version = match try!(db.collection("operations").find_one(None, Some(find_options))) {
+        Some(document) => document.get_i64("version").unwrap_or(0),
+        None => 0,
+    };
+
+    let _ = db.collection("counters").insert_one(doc!("_id" => VERSION_COUNTER, "value" => version), None);
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use bson::{self, Bson, Document};
+use clap::ArgMatches;
+use mongodb::db::{Database, ThreadedDatabase};
+use proddle::{ProddleError, VantageConfig};
+use time;
+
+use std::collections::HashMap;
+
+pub fn set(db: &Database, matches: &ArgMatches) -> Result<(), ProddleError> {
+    let (hostname, group) = try!(parse_target(matches));
+    let include_tags = match matches.values_of("INCLUDE_TAGS") {
+        Some(include_tags) => {
+            let mut hash_map = HashMap::new();
+            for include_tag in include_tags {
+                let mut split_values = include_tag.split("|");
+                let tag = try!(split_values.nth(0).ok_or("failed to parse include tag")).to_owned();
+                let interval = try!(try!(split_values.nth(0).ok_or("failed to parse include tag interval")).parse::<i64>());
+                if interval <= 0 {
+                    return Err(ProddleError::from(format!("include tag '{}' requires a positive interval", tag)));
+                }
+
+                hash_map.insert(tag, interval);
+            }
+
+            Some(hash_map)
+        },
+        None => None,
+    };
+
+    let exclude_tags: Option<Vec<String>> = matches.values_of("EXCLUDE_TAGS").map(|x| x.map(|y| y.to_owned()).collect());
+    let max_jitter_seconds = match matches.value_of("MAX_JITTER_SECONDS") {
+        Some(_) => Some(try!(value_t!(matches, "MAX_JITTER_SECONDS", i64))),
+        None => None,
+    };
+
+    //create vantage config document, the timestamp is the version reported by vantages
+    let vantage_config = VantageConfig {
+        hostname: hostname,
+        group: group,
+        timestamp: time::now_utc().to_timespec().sec,
+        include_tags: include_tags,
+        exclude_tags: exclude_tags,
+        max_jitter_seconds: max_jitter_seconds,
+    };
+
+    //replace any existing document for the vantage or group
+    let filter = target_filter(&vantage_config.hostname, &vantage_config.group);
+    if let Bson::Document(document) = try!(bson::to_bson(&vantage_config)) {
+        try!(db.collection("vantage_configs").delete_many(filter, None));
+        try!(db.collection("vantage_configs").insert_one(document, None));
+    } else {
+        return Err(ProddleError::from("failed to parse VantageConfig into OrderedDocument"));
+    }
+
+    Ok(())
+}
+
+pub fn delete(db: &Database, matches: &ArgMatches) -> Result<(), ProddleError> {
+    let (hostname, group) = try!(parse_target(matches));
+    try!(db.collection("vantage_configs").delete_many(target_filter(&hostname, &group), None));
+    Ok(())
+}
+
+fn parse_target(matches: &ArgMatches) -> Result<(Option<String>, Option<String>), ProddleError> {
+    match (matches.value_of("HOSTNAME"), matches.value_of("GROUP")) {
+        (Some(hostname), None) => Ok((Some(hostname.to_owned()), None)),
+        (None, Some(group)) => Ok((None, Some(group.to_owned()))),
+        _ => Err(ProddleError::from("exactly one of vantage hostname or group is required")),
+    }
+}
+
+fn target_filter(hostname: &Option<String>, group: &Option<String>) -> Document {
+    match (hostname, group) {
+        (&Some(ref hostname), _) => doc!("hostname" => hostname.to_owned()),
+        (_, &Some(ref group)) => doc!("group" => group.to_owned()),
+        _ => Document::new(),
+    }
+}